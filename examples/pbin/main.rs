@@ -18,7 +18,6 @@ use termimad as t;
 use nanoid::nanoid;
 use anyhow::{Context, Result};
 use tempfile::tempdir;
-use open;
 
 mod pbin;
 use weid::outcome::*;
@@ -161,24 +160,17 @@ fn do_output(outs: Vec<&Answer>) -> Result<()> {
 
 fn output_query_results(anss: Vec<String>) {
     for ans in anss.iter() {
-        stdout().write_all(&ans.as_bytes()).unwrap();
+        stdout().write_all(ans.as_bytes()).unwrap();
         stdout().write_all("\n".as_bytes()).unwrap();
     };
 }
 
-fn do_weid(ql: &mut QueryList) -> Result<()> {
+// Asks about one day's posts. Returns whether the user cancelled.
+fn do_weid(ql: &mut QueryList) -> Result<bool> {
     let mut querier = Querier::new(ql.clone());
-
-    while let Some(qid) = querier.pick_next_query() {
-        querier.mark_visited(qid);
-        let query = querier.get_query(qid.clone()).unwrap();
-        let answer = querier.execute_query(&query)?;
-        for o in answer.outcomes() {
-            let result = o.execute()?;
-        };
-    };
-
-    Ok(())
+    // going back undoes, and ending the input stops the session
+    querier.run()?;
+    Ok(querier.cancelled())
 }
 
 
@@ -200,7 +192,10 @@ fn main() {
             ql.insert_query(this_q);
         };
 
-        let out = do_weid(&mut ql);
+        // cancelling ends the whole review, not just this day
+        if do_weid(&mut ql).unwrap() {
+            break;
+        };
     };
 
 }
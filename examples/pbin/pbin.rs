@@ -3,7 +3,6 @@ use std::vec::Vec;
 use std::fmt::Debug;
 use std::thread::sleep;
 use std::time::Duration;
-use reqwest;
 use reqwest::StatusCode;
 use serde::Deserialize;
 use serde::de::DeserializeOwned;
@@ -123,7 +122,7 @@ impl PinboardClient {
                     return Ok(serde_json::from_str(&t[..])?);
                 },
                 StatusCode::TOO_MANY_REQUESTS => {
-                    sleep((&self.wait_time).clone());
+                    sleep(self.wait_time);
                     self.wait_time = self.wait_time + self.wait_time;
                     retry = true;
                 },
@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+
+use crate::prompt::{Menu, Pick, Prompter, BACK_KEY};
+use crate::querylist::AnswerId;
+
+// Reads scripted answers from a file, one per line, or from a comma
+// separated list like `1,3,2` if there is no file by that name. In a file,
+// blank lines and lines starting with `#` are skipped.
+pub fn load(spec: &str) -> Result<Vec<String>> {
+    if Path::new(spec).is_file() {
+        let text = fs::read_to_string(spec).with_context(|| format!("{}: could not read answers", spec))?;
+        return Ok(text.lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(String::from)
+            .collect());
+    };
+    Ok(spec.split(',').map(|a| a.trim().to_string()).collect())
+}
+
+// Answers queries from a list rather than asking anyone, for unattended
+// runs. Each entry is the number of an answer as the terminal would show
+// it (from 1), an answer id like `q0a1`, the text or value of the answer,
+// or `b` to go back. `LABEL=ENTRY` only fits the query with that `--id`.
+// An entry that doesn't fit the query being asked is an error, as is
+// running out of entries.
+pub struct BatchPrompter {
+    answers: VecDeque<String>,
+    // how many entries have been used, for error messages
+    used: usize,
+}
+
+impl BatchPrompter {
+    pub fn new(answers: Vec<String>) -> Self {
+        BatchPrompter {
+            answers: answers.into(),
+            used: 0,
+        }
+    }
+
+    fn pick(menu: &Menu, entry: &str) -> Option<Pick> {
+        if entry == BACK_KEY || entry == "back" {
+            return menu.can_go_back.then_some(Pick::Back);
+        };
+        if let Ok(n) = entry.parse::<usize>() {
+            return (1..=menu.answers.len()).contains(&n).then(|| Pick::Answer(n - 1));
+        };
+        if let Ok(aid) = entry.parse::<AnswerId>() {
+            return menu.answer_ids.iter().position(|a| *a == aid).map(Pick::Answer);
+        };
+        let by_text = menu.answers.iter().zip(&menu.values)
+            .position(|(text, value)| text == entry || value.as_deref() == Some(entry));
+        if let Some(i) = by_text {
+            return Some(Pick::Answer(i));
+        };
+
+        let (label, entry) = entry.split_once('=')?;
+        if menu.label.as_deref() != Some(label) {
+            return None;
+        };
+        Self::pick(menu, entry)
+    }
+}
+
+impl Prompter for BatchPrompter {
+    fn ask(&mut self, menu: &Menu) -> Result<Pick> {
+        let Some(entry) = self.answers.pop_front() else {
+            bail!("Ran out of scripted answers at query {} ({:?})", menu.qid, menu.text);
+        };
+        self.used += 1;
+
+        match Self::pick(menu, &entry) {
+            Some(pick) => Ok(pick),
+            None => bail!(
+                "Scripted answer #{} {:?} doesn't fit query {} ({:?}), whose answers are {:?}",
+                self.used, entry, menu.qid, menu.text, menu.answers,
+            ),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    fn menu(can_go_back: bool) -> Menu {
+        Menu {
+            qid: 1,
+            label: None,
+            text: "Deploy?".to_string(),
+            answers: vec!["yes".to_string(), "no".to_string()],
+            answer_ids: vec![AnswerId::new(1, 0), AnswerId::new(1, 2)],
+            values: vec![None, None],
+            can_go_back,
+        }
+    }
+
+    #[test]
+    fn scripted_answers() {
+        let entries = ["2", "q1a2", "yes", "b", "3", "q1a1", "maybe", "b"].map(String::from).to_vec();
+        let mut prompter = BatchPrompter::new(entries);
+        assert_eq!(prompter.ask(&menu(false)).unwrap(), Pick::Answer(1));
+        assert_eq!(prompter.ask(&menu(false)).unwrap(), Pick::Answer(1));
+        assert_eq!(prompter.ask(&menu(false)).unwrap(), Pick::Answer(0));
+        assert_eq!(prompter.ask(&menu(true)).unwrap(), Pick::Back);
+
+        let err = prompter.ask(&menu(false)).unwrap_err();
+        assert_eq!(err.to_string(), r#"Scripted answer #5 "3" doesn't fit query 1 ("Deploy?"), whose answers are ["yes", "no"]"#);
+        // q1a1 is hidden by its guard, so it isn't in the menu
+        assert!(prompter.ask(&menu(false)).is_err());
+        assert!(prompter.ask(&menu(false)).is_err());
+        assert!(prompter.ask(&menu(false)).is_err());
+
+        let err = prompter.ask(&menu(false)).unwrap_err();
+        assert_eq!(err.to_string(), r#"Ran out of scripted answers at query 1 ("Deploy?")"#);
+    }
+
+    #[test]
+    fn values() {
+        let mut menu = menu(false);
+        menu.values = vec![Some("deploy".to_string()), None];
+        let entries = ["deploy", "no", "yes"].map(String::from).to_vec();
+        let mut prompter = BatchPrompter::new(entries);
+        assert_eq!(prompter.ask(&menu).unwrap(), Pick::Answer(0));
+        assert_eq!(prompter.ask(&menu).unwrap(), Pick::Answer(1));
+        assert_eq!(prompter.ask(&menu).unwrap(), Pick::Answer(0));
+    }
+
+    #[test]
+    fn labels() {
+        let mut menu = menu(true);
+        menu.label = Some("deploy".to_string());
+        let entries = ["deploy=no", "deploy=1", "deploy=b", "tag=yes", "deploy=maybe"].map(String::from).to_vec();
+        let mut prompter = BatchPrompter::new(entries);
+        assert_eq!(prompter.ask(&menu).unwrap(), Pick::Answer(1));
+        assert_eq!(prompter.ask(&menu).unwrap(), Pick::Answer(0));
+        assert_eq!(prompter.ask(&menu).unwrap(), Pick::Back);
+        assert!(prompter.ask(&menu).is_err());
+        assert!(prompter.ask(&menu).is_err());
+
+        // answer text with a `=` in it is still matched whole
+        menu.answers[0] = "deploy=yes".to_string();
+        let mut prompter = BatchPrompter::new(vec!["deploy=yes".to_string()]);
+        assert_eq!(prompter.ask(&menu).unwrap(), Pick::Answer(0));
+    }
+
+    #[test]
+    fn loading() {
+        assert_eq!(load("1, 3,q0a2").unwrap(), ["1", "3", "q0a2"]);
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "# deploy\n2\n\n  yes, please\n").unwrap();
+        assert_eq!(load(file.path().to_str().unwrap()).unwrap(), ["2", "yes, please"]);
+    }
+}
@@ -7,6 +7,7 @@
 
 use std::collections::hash_map::HashMap;
 use std::cmp::PartialEq;
+use std::os::fd::RawFd;
 
 use clap_lex::{ArgCursor, RawArgs};
 use anyhow::{Result, bail};
@@ -15,17 +16,59 @@ use log::debug;
 use crate::qa::*;
 use crate::querylist::*;
 use crate::outcome::Outcome;
-
-//struct Query {
-#[derive(Clone,Debug)]
-struct Cli {
-    answers: Vec<String>,
-
-    queries: Vec<String>,
-
-    outcomes: Vec<String>,
+use crate::script::Script;
+use crate::order::Order;
+use crate::vars::Value;
+use crate::expr::Expr;
+use crate::records::{self, Record, RecordFormat};
+use crate::fifo;
+use crate::report::OutputFormat;
+use crate::querier::Fallback;
+
+// Flags that change how weid runs, rather than what it asks
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Options {
+    pub dump_script: Option<String>,
+    pub order: Order,
+    pub seed: Option<u64>,
+    // how many queries --order random asks, which it needs to ever stop
+    pub count: Option<usize>,
+    // what to do after an answer without a --goto; a script file can set
+    // it too, and whichever comes last wins
+    pub fallback: Fallback,
+    pub dump_vars: Option<String>,
+    // dmenu mode: one query, answered from the lines on stdin
+    pub dmenu: bool,
+    pub prompt: Option<String>,
+    pub ignore_case: bool,
+    pub lines: Option<usize>,
+    // one query per line of stdin, see `get_arg_queries_for_items`
+    pub each: bool,
+    // a named pipe to take more queries from while running
+    pub fifo: Option<String>,
+    // keep waiting on the pipe when there is nothing left to ask
+    pub wait: bool,
+    // JSON messages instead of a terminal, see `weid::headless`
+    pub headless: bool,
+    // the fds to read replies from and write messages to, if not stdin
+    // and stdout
+    pub headless_fds: Option<(RawFd, RawFd)>,
+    // a Unix socket or localhost address to serve the session on
+    pub serve: Option<String>,
+    // scripted answers to run through without asking, see `weid::batch`
+    pub answers: Option<String>,
+    // a transcript to write, and one to make the same choices as
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    // report what the session did on stdout, rather than just outcome output
+    pub output: Option<OutputFormat>,
+    // the query whose answer decides the exit code, see `Querier::exit_code`
+    pub exit_from: Option<String>,
 }
 
+// Flags that are either there or not, and don't take a value
+const SWITCHES: [&str; 5] = ["dmenu", "i", "each", "headless", "wait"];
+
 
 pub fn get_and_preprocess_args() -> Option<Vec<(String, String)>> {
     let raw = RawArgs::from_args();
@@ -46,6 +89,10 @@ fn _get_and_preprocess_args(raw: RawArgs) -> Option<Vec<(String, String)>> {
         //not supporting multi-flag shorts
         if a.is_short() {
             let flag = a.to_short()?.next()?.unwrap().to_string(); 
+            if SWITCHES.contains(&flag.as_str()) {
+                args.push((flag, String::new()));
+                continue;
+            };
             let val = raw.next(&mut cur)?.to_value().unwrap().to_string();
             args.push((flag,val));
         }
@@ -57,6 +104,9 @@ fn _get_and_preprocess_args(raw: RawArgs) -> Option<Vec<(String, String)>> {
                 Some(val) => {
                     args.push((flag, val.to_str()?.to_string()));
                 },
+                None if SWITCHES.contains(&flag.as_str()) => {
+                    args.push((flag, String::new()));
+                },
                 None => {
                     let val = raw.next(&mut cur)?.to_value().unwrap().to_string();
                     args.push((flag, val));
@@ -68,72 +118,422 @@ fn _get_and_preprocess_args(raw: RawArgs) -> Option<Vec<(String, String)>> {
     Some(args)
 }
 
-fn _to_querylist<'a>(args: Vec<(String, String)>) -> Result<QueryList<'a>> {
+// An answer being built from the args, plus the label its --goto points at
+type PendingAnswer<'a> = (Answer<'a>, Option<String>);
+
+// Tracks the query and answer currently being defined while walking the
+// args. Jumps can't be turned into paths until every label is known, so
+// they are collected in `gotos` and resolved at the end.
+#[derive(Default)]
+struct ArgState<'a> {
+    ql: QueryList<'a>,
+    active_q: Option<(Query<'a>, Vec<(usize, String)>)>,
+    active_a: Option<PendingAnswer<'a>>,
+    defaults: Vec<PendingAnswer<'a>>,
+    gotos: Vec<(AnswerId, String)>,
+    // --each and --records items, to be turned into queries once the
+    // default answers are all known
+    items: Vec<Items>,
+    record_format: Option<RecordFormat>,
+}
 
-    let mut ql = QueryList::new();
+// A batch of items, the position among the other queries where it was
+// given, and its --display template
+struct Items {
+    at: usize,
+    records: Vec<Record>,
+    display: Option<String>,
+}
 
-    let mut active_q: Option<Query> = None;
-    let mut active_a: Option<Answer> = None;
-    let mut defaults: Vec<Answer> = Vec::new();
+impl<'a> ArgState<'a> {
+    fn flush_answer(&mut self) {
+        if let Some((a, goto)) = self.active_a.take() {
+            if let Some((q, q_gotos)) = &mut self.active_q {
+                if let Some(label) = goto {
+                    q_gotos.push((q.answers().len(), label));
+                };
+                q.add_answer(a);
+            }
+            else {
+                self.defaults.push((a, goto));
+            };
+        };
+    }
 
-    let mut args_iter = args.iter();
+    fn flush_query(&mut self) {
+        self.flush_answer();
+        if let Some((q, q_gotos)) = self.active_q.take() {
+            let qid = self.ql.insert_query(q);
+            for (sub, label) in q_gotos {
+                self.gotos.push((AnswerId::new(qid, sub), label));
+            };
+        };
+    }
 
-    while let Some((flag, val)) = args_iter.next() {
-        if ["q".to_string(), "query".to_string()].contains(flag) {
-            if let Some(a) = active_a.take() {
-                if let Some(q) = &mut active_q {
-                    q.add_answer(a);
-                }
-                else {
-                    defaults.push(a);
+    fn with_defaults(&self, query: Query<'a>) -> (Query<'a>, Vec<(usize, String)>) {
+        let mut query = query;
+        let mut q_gotos = Vec::new();
+        for (i, (a, goto)) in self.defaults.iter().enumerate() {
+            query.add_answer(a.clone());
+            if let Some(label) = goto {
+                q_gotos.push((i, label.clone()));
+            };
+        };
+        (query, q_gotos)
+    }
+
+    fn start_query(&mut self, query: Query<'a>) {
+        self.flush_query();
+
+        let (query, q_gotos) = self.with_defaults(query);
+        debug!("{:?}",&query);
+
+        self.active_q = Some((query, q_gotos));
+    }
+
+    fn start_items(&mut self, records: Vec<Record>) {
+        self.flush_query();
+        let at = self.ql.order().len();
+        self.items.push(Items { at, records, display: None });
+    }
+
+    // One query per item with the default answers, where the items were
+    // given. `{}` and `{field}` in the display template and the answers
+    // stand for the item and its fields.
+    fn add_items(&mut self) {
+        // the last batch first, so the positions of the others still hold
+        for items in std::mem::take(&mut self.items).into_iter().rev() {
+            for (i, record) in items.records.iter().enumerate() {
+                let text = items.display.clone().unwrap_or_else(|| record.default_template());
+                let (query, q_gotos) = self.with_defaults(Query::from_text(text));
+                let qid = self.ql.insert_query_at(items.at + i, query.fill(record));
+                for (sub, label) in q_gotos {
+                    self.gotos.push((AnswerId::new(qid, sub), label));
                 };
             };
-            if let Some(q) = active_q.take() {
-                ql.insert_query(q);
+        };
+    }
+
+    fn finish(mut self) -> Result<QueryList<'a>> {
+        self.flush_query();
+
+        for (aid, label) in self.gotos {
+            let Some(target) = self.ql.find_label(&label) else {
+                bail!("Malformed arguments: --goto refers to unknown query label {:?}", label);
             };
-            let mut query = Query::from_text(val.to_string());
-            query.add_answers(defaults.clone());
-            debug!("{:?}",&query);
+            self.ql.add_path(aid, target);
+        };
+
+        Ok(self.ql)
+    }
+}
+
+fn _to_querylist<'a>(args: Vec<(String, String)>) -> Result<QueryList<'a>> {
+    _to_querylist_with_items(args, &[])
+}
+
+fn _to_querylist_with_items<'a>(args: Vec<(String, String)>, items: &[String]) -> Result<QueryList<'a>> {
+
+    let mut state = ArgState::default();
 
-            active_a = None;
-            active_q = Some(query);
+    for (flag, val) in args.iter() {
+        if ["q".to_string(), "query".to_string()].contains(flag) {
+            state.start_query(Query::from_text(val.to_string()));
         }
         else if ["a".to_string(), "answer".to_string()].contains(flag) {
-            let mut answer = Answer::from_text(val.to_string());
-
-            if let Some(a) = active_a.take() {
-                if let Some(q) = &mut active_q {
-                    q.add_answer(a);
-                }
-                else {
-                    defaults.push(a);
-                };
-            };
-            active_a = Some(answer);
-
+            state.flush_answer();
+            state.active_a = Some((Answer::from_text(val.to_string()), None));
         }
         else if ["o".to_string(), "outcome".to_string()].contains(flag) {
             let outcome = Outcome::Command(val.to_owned());
-            if let Some(ans) = &mut active_a {
+            if let Some((ans, _)) = &mut state.active_a {
                 ans.add_outcome(outcome.clone());
             }
             else {
                 //bail!("Malformed arguments: Outcome has no Answer");
             };
+        }
+        else if flag == "sh" {
+            let Some((ans, _)) = &mut state.active_a else {
+                bail!("Malformed arguments: --sh {:?} has no answer", val);
+            };
+            ans.add_outcome(Outcome::new_shell(val.to_owned()));
+        }
+        else if flag == "set" {
+            let Some((name, value)) = val.split_once('=') else {
+                bail!("Malformed arguments: --set {:?} should look like NAME=VALUE", val);
+            };
+            let Some((ans, _)) = &mut state.active_a else {
+                bail!("Malformed arguments: --set {:?} has no answer", val);
+            };
+            ans.add_outcome(Outcome::new_set_var(name, value.parse::<Value>()?));
+        }
+        else if flag == "exit" {
+            let Ok(code) = val.parse::<i32>() else {
+                bail!("Malformed arguments: --exit {:?} is not a whole number", val);
+            };
+            let Some((ans, _)) = &mut state.active_a else {
+                bail!("Malformed arguments: --exit {:?} has no answer", val);
+            };
+            if let Err(e) = ans.set_exit_code(code) {
+                bail!("Malformed arguments: --exit: {}", e);
+            };
+        }
+        else if flag == "let" {
+            let Some((name, src)) = val.split_once('=') else {
+                bail!("Malformed arguments: --let {:?} should look like NAME=EXPR", val);
+            };
+            let expr = match Expr::parse(src) {
+                Ok(expr) => expr,
+                Err(e) => bail!("Malformed arguments: --let {:?}: {}", val, e),
+            };
+            let Some((ans, _)) = &mut state.active_a else {
+                bail!("Malformed arguments: --let {:?} has no answer", val);
+            };
+            ans.add_outcome(Outcome::new_compute(name.trim(), expr));
+        }
+        else if flag == "undo" {
+            let Some(outcome) = state.active_a.as_mut().and_then(|(ans, _)| ans.pop_outcome()) else {
+                bail!("Malformed arguments: --undo {:?} has no outcome to undo", val);
+            };
+            let undo = Outcome::Command(val.to_owned());
+            state.active_a.as_mut().unwrap().0.add_outcome(outcome.with_undo(undo));
+        }
+        else if flag == "id" {
+            let Some((q, _)) = &mut state.active_q else {
+                bail!("Malformed arguments: --id {:?} has no query to name", val);
+            };
+            if state.ql.find_label(val).is_some() || q.label() == Some(val) {
+                bail!("Malformed arguments: query label {:?} is used more than once", val);
+            };
+            q.set_label(val.to_string());
+        }
+        else if flag == "value" {
+            let Some((ans, _)) = &mut state.active_a else {
+                bail!("Malformed arguments: --value {:?} has no answer", val);
+            };
+            ans.set_value(val.to_string());
+        }
+        else if flag == "key" {
+            let Some((q, _)) = &mut state.active_q else {
+                bail!("Malformed arguments: --key {:?} has no query", val);
+            };
+            q.set_key(val.to_string());
+        }
+        else if flag == "weight" {
+            let Some((q, _)) = &mut state.active_q else {
+                bail!("Malformed arguments: --weight {:?} has no query", val);
+            };
+            let Ok(weight) = val.parse() else {
+                bail!("Malformed arguments: --weight {:?} is not a number", val);
+            };
+            if let Err(e) = q.set_weight(weight) {
+                bail!("Malformed arguments: --weight: {}", e);
+            };
+        }
+        else if flag == "priority" {
+            let Some((q, _)) = &mut state.active_q else {
+                bail!("Malformed arguments: --priority {:?} has no query", val);
+            };
+            let Ok(priority) = val.parse() else {
+                bail!("Malformed arguments: --priority {:?} is not a whole number", val);
+            };
+            q.set_priority(priority);
+        }
+        else if flag == "if" {
+            let guard = match Expr::parse(val) {
+                Ok(guard) => guard,
+                Err(e) => bail!("Malformed arguments: --if {:?}: {}", val, e),
+            };
+            if let Some((ans, _)) = &mut state.active_a {
+                ans.set_guard(guard);
+            }
+            else if let Some((q, _)) = &mut state.active_q {
+                q.set_guard(guard);
+            }
+            else {
+                bail!("Malformed arguments: --if {:?} has no query or answer to guard", val);
+            };
+        }
+        else if flag == "goto" {
+            let Some((_, goto)) = &mut state.active_a else {
+                bail!("Malformed arguments: --goto {:?} has no answer to attach to", val);
+            };
+            *goto = Some(val.to_string());
+        }
+        else if flag == "each" {
+            state.start_items(items.iter().map(|item| Record::from_line(item)).collect());
+        }
+        else if flag == "format" {
+            state.record_format = Some(val.parse()?);
+        }
+        else if flag == "records" {
+            let records = records::load(val, state.record_format)?;
+            state.start_items(records);
+        }
+        else if flag == "display" {
+            let Some(items) = state.items.last_mut() else {
+                bail!("Malformed arguments: --display {:?} has no --each or --records to show", val);
+            };
+            items.display = Some(val.to_string());
+        }
+        // a named pipe isn't a script, but a control channel; see _to_options
+        else if ["f".to_string(), "file".to_string()].contains(flag) && !fifo::is_fifo(val) {
+            state.flush_query();
+            state.ql.append(Script::load(val)?.to_querylist());
         };
     };
 
-    if let Some(a) = active_a.take() {
-        if let Some(q) = &mut active_q {
-            q.add_answer(a);
+    state.flush_query();
+    state.add_items();
+    state.finish()
+}
+
+fn _to_options(args: &[(String, String)]) -> Result<Options> {
+    let mut opts = Options::default();
+    let mut records_from_stdin = false;
+
+    for (flag, val) in args.iter() {
+        if flag == "dump-script" {
+            opts.dump_script = Some(val.to_string());
+        }
+        else if flag == "dump-vars" {
+            opts.dump_vars = Some(val.to_string());
+        }
+        else if flag == "order" {
+            opts.order = val.parse()?;
+        }
+        else if flag == "count" {
+            let Ok(count) = val.parse() else {
+                bail!("Malformed arguments: --count {:?} is not a whole number", val);
+            };
+            opts.count = Some(count);
+        }
+        else if flag == "seed" {
+            let Ok(seed) = val.parse() else {
+                bail!("Malformed arguments: --seed {:?} is not a whole number", val);
+            };
+            opts.seed = Some(seed);
+        }
+        else if flag == "dmenu" {
+            opts.dmenu = true;
+        }
+        else if flag == "p" || flag == "prompt" {
+            opts.prompt = Some(val.to_string());
+        }
+        else if flag == "i" {
+            opts.ignore_case = true;
+        }
+        else if flag == "l" || flag == "lines" {
+            let Ok(lines) = val.parse() else {
+                bail!("Malformed arguments: -l {:?} is not a whole number", val);
+            };
+            opts.lines = Some(lines);
+        }
+        else if flag == "each" {
+            opts.each = true;
+        }
+        else if flag == "records" {
+            records_from_stdin |= val == "-";
+        }
+        else if flag == "fallback" {
+            opts.fallback = val.parse()?;
+        }
+        else if flag == "fifo" || ((flag == "f" || flag == "file") && fifo::is_fifo(val)) {
+            opts.fifo = Some(val.to_string());
+        }
+        else if flag == "wait" {
+            opts.wait = true;
+        }
+        else if flag == "f" || flag == "file" {
+            if let Some(fallback) = Script::load(val)?.fallback() {
+                opts.fallback = fallback;
+            };
+        }
+        else if flag == "exit-from" {
+            opts.exit_from = Some(val.to_string());
+        }
+        else if flag == "output" {
+            opts.output = Some(val.parse()?);
+        }
+        else if flag == "record" {
+            opts.record = Some(val.to_string());
         }
+        else if flag == "replay" {
+            opts.replay = Some(val.to_string());
+        }
+        else if flag == "answers" {
+            opts.answers = Some(val.to_string());
+        }
+        else if flag == "serve" {
+            opts.serve = Some(val.to_string());
+        }
+        else if flag == "headless" {
+            opts.headless = true;
+            if !val.is_empty() {
+                let fds = val.split_once(',').and_then(|(i, o)| Some((i.parse().ok()?, o.parse().ok()?)));
+                let Some(fds) = fds else {
+                    bail!("Malformed arguments: --headless={:?} should be two fds, like --headless=3,4", val);
+                };
+                opts.headless_fds = Some(fds);
+            };
+        };
+    };
+
+    // a served session is answered by its clients and runs until weid is
+    // stopped, so nothing else can answer it or act on how it ended
+    if opts.serve.is_some() {
+        let given = [
+            ("--dump-vars", opts.dump_vars.is_some()),
+            ("--output", opts.output.is_some()),
+            ("--exit-from", opts.exit_from.is_some()),
+            ("--record", opts.record.is_some()),
+            ("--replay", opts.replay.is_some()),
+            ("--answers", opts.answers.is_some()),
+            ("--headless", opts.headless),
+            ("--fifo", opts.fifo.is_some()),
+        ];
+        if let Some((flag, _)) = given.iter().find(|(_, given)| *given) {
+            bail!("Malformed arguments: {} can't be used with --serve", flag);
+        };
+    };
+
+    // each of these answers the queries, so only one of them can
+    let answering: Vec<_> = [("--headless", opts.headless), ("--answers", opts.answers.is_some()), ("--replay", opts.replay.is_some())]
+        .into_iter()
+        .filter_map(|(flag, given)| given.then_some(flag))
+        .collect();
+    if answering.len() > 1 {
+        bail!("Malformed arguments: {} can't be used together", answering.join(" and "));
+    };
+
+    if opts.headless && opts.output.is_some() {
+        bail!("Malformed arguments: --output can't be used with --headless");
+    };
+
+    if opts.wait && opts.fifo.is_none() {
+        bail!("Malformed arguments: --wait needs a --fifo to wait on");
     };
 
-    if let Some(q) = active_q.take() {
-        ql.insert_query(q);
+    if opts.each && records_from_stdin {
+        bail!("Malformed arguments: --each and --records - both read stdin");
     };
 
-    Ok(ql)
+    match (opts.order, opts.count) {
+        (Order::Random, None) => bail!("Malformed arguments: --order random needs a --count, or it would never stop"),
+        (Order::Random, _) | (_, None) => {},
+        (_, Some(_)) => bail!("Malformed arguments: --count is only for --order random"),
+    };
+
+    Ok(opts)
+}
+
+pub fn get_arg_options() -> Result<Options> {
+    let Some(args) = get_and_preprocess_args() else {
+        bail!("Malformed arguments");
+    };
+
+    _to_options(&args)
 }
 
 pub fn get_arg_queries<'a>() -> Result<QueryList<'a>> {
@@ -152,6 +552,15 @@ pub fn get_arg_queries<'a>() -> Result<QueryList<'a>> {
     _to_querylist(args)
 }
 
+// For --each: the queries from the args, followed by one per item
+pub fn get_arg_queries_for_items<'a>(items: &[String]) -> Result<QueryList<'a>> {
+    let Some(args) = get_and_preprocess_args() else {
+        bail!("Malformed arguments");
+    };
+
+    _to_querylist_with_items(args, items)
+}
+
 
 #[cfg(test)]
 mod test {
@@ -197,11 +606,354 @@ mod test {
         let out = _to_querylist(args).unwrap();
         assert_eq!(&correct, out.peek_queries());
     }
+
+    #[test]
+    fn args_to_options() {
+        let args_raw = "-q q1 -a a1 --dump-script flow.toml".split(" ");
+        let args = _get_and_preprocess_args(RawArgs::new(args_raw)).unwrap();
+
+        let opts = _to_options(&args).unwrap();
+        assert_eq!(opts.dump_script, Some("flow.toml".to_string()));
+        assert_eq!(opts.order, Order::Sequential);
+
+        let out = _to_querylist(args).unwrap();
+        assert_eq!(out.peek_queries().len(), 1);
+
+        let args_raw = "--order shuffle --seed 42 -q q1 --priority 3 --weight 0.5".split(" ");
+        let args = _get_and_preprocess_args(RawArgs::new(args_raw)).unwrap();
+        let opts = _to_options(&args).unwrap();
+        assert_eq!(opts.order, Order::Shuffle);
+        assert_eq!(opts.seed, Some(42));
+
+        let out = _to_querylist(args).unwrap();
+        let q1 = out.get_query(0).unwrap();
+        assert_eq!(q1.priority(), 3);
+        assert_eq!(q1.weight(), 0.5);
+
+        let args_raw = "--order upside-down".split(" ");
+        let args = _get_and_preprocess_args(RawArgs::new(args_raw)).unwrap();
+        assert!(_to_options(&args).is_err());
+
+        let args = _get_and_preprocess_args(RawArgs::new("--order random --count 20".split(" "))).unwrap();
+        assert_eq!(_to_options(&args).unwrap().count, Some(20));
+        for args_raw in ["--order random", "--count 20", "-q q1 --weight inf", "-q q1 --weight -1"] {
+            let args = _get_and_preprocess_args(RawArgs::new(args_raw.split(" "))).unwrap();
+            assert!(_to_options(&args).is_err() || _to_querylist(args).is_err(), "{}", args_raw);
+        };
+
+        // a script can set the fallback, and a later flag overrides it
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("flow.toml");
+        std::fs::write(&path, "fallback = \"stop\"\n[[queries]]\ntext = \"q1\"\n").unwrap();
+        let path = path.display().to_string();
+        let args = _get_and_preprocess_args(RawArgs::new(["-f", &path])).unwrap();
+        assert_eq!(_to_options(&args).unwrap().fallback, Fallback::Stop);
+        let args = _get_and_preprocess_args(RawArgs::new(["-f", &path, "--fallback", "next"])).unwrap();
+        assert_eq!(_to_options(&args).unwrap().fallback, Fallback::Next);
+        let args = _get_and_preprocess_args(RawArgs::new(["--fallback", "sideways"])).unwrap();
+        assert!(_to_options(&args).is_err());
+    }
+
+    #[test]
+    fn args_for_each() {
+        let args_raw = ["-a", "keep", "-a", "delete {}", "-o", "rm {}", "--undo", "touch {}", "--goto", "done",
+                        "-q", "Ready?", "--each", "-q", "All done.", "--id", "done"];
+        let args = _get_and_preprocess_args(RawArgs::new(args_raw)).unwrap();
+        assert!(_to_options(&args).unwrap().each);
+
+        let items = ["notes.txt".to_string(), "old.log".to_string()];
+        let ql = _to_querylist_with_items(args.clone(), &items).unwrap();
+
+        assert_eq!(ql.order().len(), 4);
+        assert_eq!(ql.get_query(ql.order()[1]).unwrap().display(), "notes.txt");
+        let q = ql.get_query(ql.order()[2]).unwrap();
+        assert_eq!(q.display(), "old.log");
+        let delete = &q.answers()[1];
+        assert_eq!(delete.display(), "delete old.log");
+        assert!(matches!(&delete.outcomes()[0], Outcome::Undoable { run, undo }
+            if matches!(&**run, Outcome::Command(c) if c == "rm old.log")
+            && matches!(&**undo, Outcome::Command(c) if c == "touch old.log")));
+        assert_eq!(ql.get_path(AnswerId::new(ql.order()[1], 1)), ql.find_label("done"));
+
+        // without --each the items are ignored
+        let args: Vec<_> = args.into_iter().filter(|(flag, _)| flag != "each").collect();
+        assert_eq!(_to_querylist_with_items(args, &items).unwrap().order().len(), 2);
+
+        let args = _get_and_preprocess_args(RawArgs::new(["--each", "-q", "{}", "--records", "-"])).unwrap();
+        assert!(_to_options(&args).is_err());
+    }
+
+    #[test]
+    fn args_for_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("posts.csv");
+        std::fs::write(&path, "href,description\nhttps://weid.dev,weid\nhttps://example.com,\"Example, Inc\"\n").unwrap();
+        let path = path.display().to_string();
+
+        let args_raw = [
+            "-a", "open", "-o", "xdg-open {href}", "-a", "skip",
+            "--records", &path, "--display", "# {description}\n{href}",
+            "-q", "Done?",
+        ];
+        let args = _get_and_preprocess_args(RawArgs::new(args_raw)).unwrap();
+        let ql = _to_querylist(args).unwrap();
+
+        let texts: Vec<String> = ql.order().iter().map(|qid| ql.get_query(*qid).unwrap().display().clone()).collect();
+        assert_eq!(texts, ["# weid\nhttps://weid.dev", "# Example, Inc\nhttps://example.com", "Done?"]);
+        let open = &ql.get_query(ql.order()[1]).unwrap().answers()[0];
+        assert!(matches!(&open.outcomes()[0], Outcome::Command(c) if c == "xdg-open https://example.com"));
+
+        // without --display the fields are listed
+        let args = _get_and_preprocess_args(RawArgs::new(["--format", "csv", "--records", &path])).unwrap();
+        let ql = _to_querylist(args).unwrap();
+        assert_eq!(ql.get_query(0).unwrap().display(), "- **href**: https://weid.dev\n- **description**: weid");
+
+        for args_raw in [vec!["--display", "{x}"], vec!["--format", "xml"], vec!["--records", "/nonexistent.jsonl"]] {
+            let args = _get_and_preprocess_args(RawArgs::new(args_raw.clone())).unwrap();
+            assert!(_to_querylist(args).is_err(), "{:?}", args_raw);
+        };
+    }
+
+    #[test]
+    fn args_with_fifo() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("weid.fifo");
+        assert!(std::process::Command::new("mkfifo").arg(&path).status().unwrap().success());
+        let path = path.display().to_string();
+
+        let args = _get_and_preprocess_args(RawArgs::new(["-f", &path, "-q", "q1"])).unwrap();
+        assert_eq!(_to_options(&args).unwrap().fifo, Some(path.clone()));
+        assert_eq!(_to_querylist(args).unwrap().order().len(), 1);
+
+        let args = _get_and_preprocess_args(RawArgs::new(["--fifo", "weid.fifo"])).unwrap();
+        assert_eq!(_to_options(&args).unwrap().fifo, Some("weid.fifo".to_string()));
+        assert!(!_to_options(&args).unwrap().wait);
+
+        let args = _get_and_preprocess_args(RawArgs::new(["--fifo", "weid.fifo", "--wait"])).unwrap();
+        assert!(_to_options(&args).unwrap().wait);
+        let args = _get_and_preprocess_args(RawArgs::new(["--wait", "-q", "q1"])).unwrap();
+        assert!(_to_options(&args).is_err());
+    }
+
+    #[test]
+    fn args_for_drivers() {
+        let args = _get_and_preprocess_args(RawArgs::new(["--headless", "-q", "q1"])).unwrap();
+        let opts = _to_options(&args).unwrap();
+        assert!(opts.headless);
+        assert_eq!(opts.headless_fds, None);
+        assert_eq!(_to_querylist(args).unwrap().order().len(), 1);
+
+        let args = _get_and_preprocess_args(RawArgs::new(["--headless=3,4"])).unwrap();
+        assert_eq!(_to_options(&args).unwrap().headless_fds, Some((3, 4)));
+        let args = _get_and_preprocess_args(RawArgs::new(["--headless=3"])).unwrap();
+        assert!(_to_options(&args).is_err());
+
+        let args = _get_and_preprocess_args(RawArgs::new(["--answers", "1,2", "-q", "q1"])).unwrap();
+        assert_eq!(_to_options(&args).unwrap().answers, Some("1,2".to_string()));
+        assert_eq!(_to_querylist(args).unwrap().order().len(), 1);
+        for args_raw in [vec!["--headless", "--answers", "1"], vec!["--answers", "1", "--replay", "old.jsonl"], vec!["--replay", "old.jsonl", "--headless"]] {
+            let args = _get_and_preprocess_args(RawArgs::new(args_raw.clone())).unwrap();
+            assert!(_to_options(&args).is_err(), "{:?}", args_raw);
+        };
+
+        let args = _get_and_preprocess_args(RawArgs::new(["--replay", "old.jsonl", "--record", "new.jsonl"])).unwrap();
+        let opts = _to_options(&args).unwrap();
+        assert_eq!(opts.replay, Some("old.jsonl".to_string()));
+        assert_eq!(opts.record, Some("new.jsonl".to_string()));
+
+        let args = _get_and_preprocess_args(RawArgs::new(["--output", "jsonl"])).unwrap();
+        assert_eq!(_to_options(&args).unwrap().output, Some(OutputFormat::JsonLines));
+        let args = _get_and_preprocess_args(RawArgs::new(["--output=xml"])).unwrap();
+        assert!(_to_options(&args).is_err());
+        let args = _get_and_preprocess_args(RawArgs::new(["--output", "json", "--headless"])).unwrap();
+        assert!(_to_options(&args).is_err());
+
+        let args = _get_and_preprocess_args(RawArgs::new(["--serve", "127.0.0.1:7777", "-q", "q1"])).unwrap();
+        assert_eq!(_to_options(&args).unwrap().serve, Some("127.0.0.1:7777".to_string()));
+        for flag in ["--dump-vars", "--output", "--exit-from", "--record"] {
+            let value = if flag == "--output" { "json" } else { "x" };
+            let args = _get_and_preprocess_args(RawArgs::new(["--serve", "weid.sock", flag, value])).unwrap();
+            assert!(_to_options(&args).is_err(), "{}", flag);
+        };
+        assert_eq!(_to_querylist(args).unwrap().order().len(), 1);
+    }
+
+    #[test]
+    fn args_for_dmenu() {
+        let args_raw = ["--dmenu", "-i", "-p", "Pick one", "-l", "5"];
+        let args = _get_and_preprocess_args(RawArgs::new(args_raw)).unwrap();
+        let opts = _to_options(&args).unwrap();
+        assert!(opts.dmenu && opts.ignore_case);
+        assert_eq!(opts.prompt, Some("Pick one".to_string()));
+        assert_eq!(opts.lines, Some(5));
+
+        let args = _get_and_preprocess_args(RawArgs::new(["-i", "--dmenu"])).unwrap();
+        assert_eq!(args, [("i".to_string(), "".to_string()), ("dmenu".to_string(), "".to_string())]);
+
+        let args = _get_and_preprocess_args(RawArgs::new(["--dmenu", "-l", "many"])).unwrap();
+        assert!(_to_options(&args).is_err());
+    }
+
+    #[test]
+    fn args_with_undo() {
+        let args_raw = ["-q", "q1", "-a", "a1", "-o", "touch x", "--undo", "rm x"];
+        let args = _get_and_preprocess_args(RawArgs::new(args_raw)).unwrap();
+        let ql = _to_querylist(args).unwrap();
+
+        let outcomes = ql.get_answer(AnswerId::new(0, 0)).unwrap().outcomes();
+        assert_eq!(outcomes.len(), 1);
+        assert!(matches!(outcomes[0].undo(), Some(Outcome::Command(c)) if c == "rm x"));
+
+        let args_raw = ["-q", "q1", "-a", "a1", "--sh", "ls | wc -l"];
+        let args = _get_and_preprocess_args(RawArgs::new(args_raw)).unwrap();
+        let ql = _to_querylist(args).unwrap();
+        assert!(matches!(&ql.get_query(0).unwrap().answers()[0].outcomes()[0], Outcome::Shell(c) if c == "ls | wc -l"));
+
+        let args = _get_and_preprocess_args(RawArgs::new("-q q1 -a a1 --undo x".split(" "))).unwrap();
+        assert!(_to_querylist(args).is_err());
+    }
+
+    #[test]
+    fn args_with_vars() {
+        let args_raw = "-q q1 -a a1 --set target=prod --set count=3 --dump-vars -".split(" ");
+        let args = _get_and_preprocess_args(RawArgs::new(args_raw)).unwrap();
+        assert_eq!(_to_options(&args).unwrap().dump_vars, Some("-".to_string()));
+
+        let ql = _to_querylist(args).unwrap();
+        let outcomes = ql.get_answer(AnswerId::new(0, 0)).unwrap().outcomes();
+        assert!(matches!(&outcomes[1], Outcome::SetVar { name, value: Value::Int(3) } if name == "count"));
+
+        let args = _get_and_preprocess_args(RawArgs::new("-q q1 -a a1 --set oops".split(" "))).unwrap();
+        assert!(_to_querylist(args).is_err());
+
+        let args = _get_and_preprocess_args(RawArgs::new(["-q", "q1", "-a", "a1", "--let", "score=score + 1"])).unwrap();
+        let ql = _to_querylist(args).unwrap();
+        let outcomes = ql.get_answer(AnswerId::new(0, 0)).unwrap().outcomes();
+        assert!(matches!(&outcomes[0], Outcome::Compute { name, expr } if name == "score" && expr.source() == "score + 1"));
+
+        for args_raw in ["-q q1 --let x=1", "-q q1 -a a1 --let x=1+", "-q q1 -a a1 --let x"] {
+            let args = _get_and_preprocess_args(RawArgs::new(args_raw.split(" "))).unwrap();
+            assert!(_to_querylist(args).is_err(), "{}", args_raw);
+        };
+    }
+
+    #[test]
+    fn args_with_labels() {
+        let args_raw = [
+            "-a", "quit", "--goto", "end",
+            "-q", "q1", "--id", "start", "-a", "again", "--goto", "start", "-a", "next",
+            "-q", "q2",
+            "-q", "q3", "--id", "end",
+        ];
+        let args = _get_and_preprocess_args(RawArgs::new(args_raw)).unwrap();
+        let ql = _to_querylist(args).unwrap();
+
+        assert_eq!(ql.find_label("start"), Some(0));
+        assert_eq!(ql.find_label("end"), Some(2));
+        assert_eq!(ql.get_path(AnswerId::new(0, 0)), Some(2));
+        assert_eq!(ql.get_path(AnswerId::new(0, 1)), Some(0));
+        assert_eq!(ql.get_path(AnswerId::new(0, 2)), None);
+        assert_eq!(ql.get_path(AnswerId::new(1, 0)), Some(2));
+        assert_eq!(ql.get_path(AnswerId::new(2, 0)), Some(2));
+    }
+
+    #[test]
+    fn args_with_bad_labels() {
+        let bad = [
+            "-q q1 -a a1 --goto nowhere",
+            "--id start -q q1",
+            "-q q1 --goto start",
+            "-q q1 --id start -q q2 --id start",
+        ];
+        for args_raw in bad {
+            let args = _get_and_preprocess_args(RawArgs::new(args_raw.split(" "))).unwrap();
+            assert!(_to_querylist(args).is_err(), "{}", args_raw);
+        };
+    }
+
+    #[test]
+    fn args_with_guards() {
+        let args = vec![
+            ("q".to_string(), "deploy?".to_string()),
+            ("id".to_string(), "deploy".to_string()),
+            ("a".to_string(), "yes".to_string()),
+            ("q".to_string(), "target?".to_string()),
+            ("if".to_string(), "answer(deploy) == 'yes'".to_string()),
+            ("a".to_string(), "prod".to_string()),
+            ("if".to_string(), "!staging_only".to_string()),
+        ];
+
+        let out = _to_querylist(args).unwrap();
+        let target = out.get_query(1).unwrap();
+        assert_eq!(target.guard().unwrap().source(), "answer(deploy) == 'yes'");
+        assert_eq!(target.answers()[0].guard().unwrap().source(), "!staging_only");
+        assert!(out.get_query(0).unwrap().guard().is_none());
+
+        for args_raw in ["--if true -q q1", "-q q1 --if answer(q1)=="] {
+            let args = _get_and_preprocess_args(RawArgs::new(args_raw.split(" "))).unwrap();
+            assert!(_to_querylist(args).is_err(), "{}", args_raw);
+        };
+    }
+
+    #[test]
+    fn args_with_values() {
+        let args_raw = ["-q", "What now?", "--key", "action", "-a", "Delete it (irreversible)", "--value", "delete", "-a", "x == y", "-a", "a=b=c"];
+        let args = _get_and_preprocess_args(RawArgs::new(args_raw)).unwrap();
+        let ql = _to_querylist(args).unwrap();
+        let q = ql.get_query(0).unwrap();
+        assert_eq!(q.key(), Some(&"action".to_string()));
+        assert_eq!(ql.find_query("action"), Some(0));
+
+        let answers = q.answers();
+        assert_eq!((answers[0].display(), answers[0].value()), ("Delete it (irreversible)".to_string(), "delete".to_string()));
+        // an answer's text is taken as it is
+        assert_eq!((answers[1].display(), answers[1].value()), ("x == y".to_string(), "x == y".to_string()));
+        assert_eq!((answers[2].display(), answers[2].value()), ("a=b=c".to_string(), "a=b=c".to_string()));
+        assert_eq!(answers[0], Answer::from_text("delete".to_string()));
+
+        let args = _get_and_preprocess_args(RawArgs::new(["-q", "q1", "--value", "v"])).unwrap();
+        assert!(_to_querylist(args).is_err());
+    }
+
+    #[test]
+    fn args_with_exit_codes() {
+        let args_raw = ["-q", "deploy?", "--id", "deploy", "-a", "yes", "-a", "no", "--exit", "1", "--exit-from", "deploy"];
+        let args = _get_and_preprocess_args(RawArgs::new(args_raw)).unwrap();
+        assert_eq!(_to_options(&args).unwrap().exit_from, Some("deploy".to_string()));
+        let out = _to_querylist(args).unwrap();
+        let answers = out.get_query(0).unwrap().answers();
+        assert_eq!(answers[0].exit_code(), None);
+        assert_eq!(answers[1].exit_code(), Some(1));
+
+        for args_raw in ["--exit 1 -q q1", "-q q1 -a a --exit 256", "-q q1 -a a --exit 130", "-q q1 -a a --exit no"] {
+            let args = _get_and_preprocess_args(RawArgs::new(args_raw.split(" "))).unwrap();
+            assert!(_to_querylist(args).is_err(), "{}", args_raw);
+        };
+    }
+
+    #[test]
+    fn args_with_script_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("flow.toml");
+        std::fs::write(&path, "[[queries]]\ntext = \"q2\"\n").unwrap();
+
+        let args = vec![
+            ("q".to_string(), "q1".to_string()),
+            ("a".to_string(), "a1".to_string()),
+            ("file".to_string(), path.display().to_string()),
+            ("q".to_string(), "q3".to_string()),
+        ];
+
+        let out = _to_querylist(args).unwrap();
+        let texts: Vec<String> = (0..3).map(|i| out.get_query(i).unwrap().display().clone()).collect();
+        assert_eq!(texts, ["q1", "q2", "q3"]);
+    }
 }
 
 // focus on cli? :
 //
 // $ weid -i questions.txt -a "yes" -a "no" -c "ls"
+// $ weid -f flow.toml -q "one more question?" -a "ok"
 // $ weid -a "yes" -a "no" -o exit -q "question 1?" -q "question 2?" -a "yes" -c "ls"
 // $ weid -f "weid.fifo"
 // $ weid -f "weid.fifo" -q "question 1?" -a "yes" -a "no"
@@ -0,0 +1,191 @@
+use std::io::{BufRead, Write};
+
+use anyhow::Result;
+
+use crate::prompt::*;
+use crate::qa::*;
+use crate::querylist::*;
+
+// Reads one menu item per line, the way dmenu does. Blank lines are
+// dropped, since there would be nothing to show for them.
+pub fn read_items<R: BufRead>(input: R) -> Result<Vec<String>> {
+    let mut items = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if !line.trim().is_empty() {
+            items.push(line.to_string());
+        };
+    };
+    Ok(items)
+}
+
+// A session of one query, with an answer for each item
+pub fn to_querylist<'a>(prompt: &str, items: Vec<String>) -> QueryList<'a> {
+    let mut q = Query::from_text(prompt.to_string());
+    q.add_answers(items.into_iter().map(Answer::from_text));
+
+    let mut ql = QueryList::new();
+    ql.insert_query(q);
+    ql
+}
+
+// A line-based take on dmenu: typing narrows the list down to the items
+// containing the text, a number picks from the list as shown, an empty
+// line picks the first item shown, and end of input cancels. Anything that
+// leaves a single item (or matches one exactly) picks it.
+pub struct DmenuPrompter<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    ignore_case: bool,
+    // how many items to list at once; all of them if None
+    lines: Option<usize>,
+}
+
+impl<R: BufRead, W: Write> DmenuPrompter<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        DmenuPrompter {
+            input,
+            output,
+            ignore_case: false,
+            lines: None,
+        }
+    }
+
+    pub fn set_ignore_case(&mut self, ignore_case: bool) {
+        self.ignore_case = ignore_case;
+    }
+
+    pub fn set_lines(&mut self, lines: Option<usize>) {
+        self.lines = lines;
+    }
+
+    fn fold(&self, s: &str) -> String {
+        if self.ignore_case {
+            s.to_lowercase()
+        }
+        else {
+            s.to_string()
+        }
+    }
+
+    // Indexes of the answers that contain `filter`
+    fn matching(&self, answers: &[String], filter: &str) -> Vec<usize> {
+        let filter = self.fold(filter);
+        (0..answers.len()).filter(|i| self.fold(&answers[*i]).contains(&filter)).collect()
+    }
+
+    fn show(&mut self, menu: &Menu, filter: &str, matches: &[usize]) -> Result<usize> {
+        let shown = self.lines.unwrap_or(matches.len()).min(matches.len());
+
+        if !menu.text.is_empty() {
+            writeln!(self.output, "{}", menu.text)?;
+        };
+        for (n, i) in matches[..shown].iter().enumerate() {
+            writeln!(self.output, "[{}] {}", n + 1, menu.answers[*i])?;
+        };
+        if shown < matches.len() {
+            writeln!(self.output, "... {} more", matches.len() - shown)?;
+        };
+        write!(self.output, "{}> ", filter)?;
+        self.output.flush()?;
+        Ok(shown)
+    }
+}
+
+impl<R: BufRead, W: Write> Prompter for DmenuPrompter<R, W> {
+    fn ask(&mut self, menu: &Menu) -> Result<Pick> {
+        let mut filter = String::new();
+
+        loop {
+            let matches = self.matching(&menu.answers, &filter);
+            let shown = self.show(menu, &filter, &matches)?;
+
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                writeln!(self.output)?;
+                return Ok(Pick::Cancel);
+            };
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                if let Some(i) = matches.first() {
+                    return Ok(Pick::Answer(*i));
+                };
+                continue;
+            };
+            if let Some(i) = menu.answers.iter().position(|a| self.fold(a) == self.fold(line)) {
+                return Ok(Pick::Answer(i));
+            };
+            if let Ok(n) = line.parse::<usize>() {
+                if (1..=shown).contains(&n) {
+                    return Ok(Pick::Answer(matches[n - 1]));
+                };
+            };
+
+            match self.matching(&menu.answers, line)[..] {
+                [] => writeln!(self.output, "no match for {:?}", line)?,
+                [i] => return Ok(Pick::Answer(i)),
+                _ => filter = line.to_string(),
+            };
+        };
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn menu() -> Menu {
+        Menu {
+            qid: 0,
+            label: None,
+            text: "Pick one".to_string(),
+            answers: ["README.md", "src/lib.rs", "src/main.rs", "Cargo.toml"].map(String::from).to_vec(),
+            answer_ids: (0..4).map(|sub| AnswerId::new(0, sub)).collect(),
+            values: vec![None; 4],
+            can_go_back: false,
+        }
+    }
+
+    fn pick(typed: &str, ignore_case: bool, lines: Option<usize>) -> (Pick, String) {
+        let mut out = Vec::new();
+        let mut prompter = DmenuPrompter::new(typed.as_bytes(), &mut out);
+        prompter.set_ignore_case(ignore_case);
+        prompter.set_lines(lines);
+        let pick = prompter.ask(&menu()).unwrap();
+        (pick, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn items() {
+        let items = read_items("a\r\n\nb c\n  \n".as_bytes()).unwrap();
+        assert_eq!(items, ["a", "b c"]);
+
+        let ql = to_querylist("Pick", items);
+        assert_eq!(ql.get_query(0).unwrap().display(), "Pick");
+        assert_eq!(ql.get_answer(AnswerId::new(0, 1)).unwrap().display(), "b c");
+    }
+
+    #[test]
+    fn picking() {
+        assert_eq!(pick("\n", false, None).0, Pick::Answer(0));
+        assert_eq!(pick("3\n", false, None).0, Pick::Answer(2));
+        assert_eq!(pick("Cargo.toml\n", false, None).0, Pick::Answer(3));
+        assert_eq!(pick("lib\n", false, None).0, Pick::Answer(1));
+        assert_eq!(pick("src\n2\n", false, None).0, Pick::Answer(2));
+        assert_eq!(pick("src\n\n", false, None).0, Pick::Answer(1));
+        assert_eq!(pick("", false, None).0, Pick::Cancel);
+
+        // case
+        assert_eq!(pick("readme\n", false, None).0, Pick::Cancel);
+        assert_eq!(pick("readme\n", true, None).0, Pick::Answer(0));
+        assert_eq!(pick("CARGO.TOML\n", true, None).0, Pick::Answer(3));
+
+        // only `lines` items are listed, and only those can be picked by number
+        let (p, out) = pick("4\n", false, Some(2));
+        assert_eq!(p, Pick::Cancel);
+        assert!(out.starts_with("Pick one\n[1] README.md\n[2] src/lib.rs\n... 2 more\n> "), "{}", out);
+    }
+}
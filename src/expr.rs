@@ -0,0 +1,651 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::vars::{Value, Vars};
+
+// What an expression can see of the running session. Queries are referred
+// to by label, or as `q<id>` if they have none.
+pub trait Env {
+    fn var(&self, name: &str) -> Option<Value>;
+    fn answer(&self, query: &str) -> Option<Value>;
+    fn visited(&self, query: &str) -> bool;
+    fn status(&self, query: &str) -> Option<Value>;
+    // an environment variable, for `{{env.NAME}}` placeholders
+    fn env_var(&self, name: &str) -> Option<String> {
+        std::env::var(name).ok()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExprError {
+    pos: usize,
+    msg: String,
+}
+
+impl ExprError {
+    fn new<S: Into<String>>(pos: usize, msg: S) -> Self {
+        ExprError { pos, msg: msg.into() }
+    }
+
+    // Byte offset into the expression text
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at position {}: {}", self.pos, self.msg)
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+// Binary operators, from loosest to tightest binding:
+// `||`, `&&`, comparisons, `+ -`, `* / %`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Tok {
+    Str(String),
+    Num(Value),
+    Ident(String),
+    LParen,
+    RParen,
+    Comma,
+    Not,
+    Op(Op),
+}
+
+fn tokenize(src: &str) -> Result<Vec<(usize, Tok)>, ExprError> {
+    let mut toks = Vec::new();
+    let mut chars = src.char_indices().peekable();
+
+    while let Some(&(pos, c)) = chars.peek() {
+        let two = |s: &str| src[pos..].starts_with(s);
+        let tok = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            },
+            '(' => Tok::LParen,
+            ')' => Tok::RParen,
+            ',' => Tok::Comma,
+            '&' if two("&&") => Tok::Op(Op::And),
+            '|' if two("||") => Tok::Op(Op::Or),
+            '=' if two("==") => Tok::Op(Op::Eq),
+            '!' if two("!=") => Tok::Op(Op::Ne),
+            '!' => Tok::Not,
+            '<' if two("<=") => Tok::Op(Op::Le),
+            '<' => Tok::Op(Op::Lt),
+            '>' if two(">=") => Tok::Op(Op::Ge),
+            '>' => Tok::Op(Op::Gt),
+            '+' => Tok::Op(Op::Add),
+            '-' => Tok::Op(Op::Sub),
+            '*' => Tok::Op(Op::Mul),
+            '/' => Tok::Op(Op::Div),
+            '%' => Tok::Op(Op::Rem),
+            '"' | '\'' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(ExprError::new(pos, "unterminated string")),
+                        Some((_, q)) if q == c => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, e)) => s.push(e),
+                            None => return Err(ExprError::new(pos, "unterminated string")),
+                        },
+                        Some((_, ch)) => s.push(ch),
+                    };
+                };
+                toks.push((pos, Tok::Str(s)));
+                continue;
+            },
+            c if c.is_ascii_digit() => {
+                let mut end = pos;
+                while let Some(&(i, d)) = chars.peek() {
+                    if !(d.is_ascii_digit() || d == '.') {
+                        break;
+                    };
+                    end = i + d.len_utf8();
+                    chars.next();
+                };
+                let text = &src[pos..end];
+                let num = match (text.parse::<i64>(), text.parse::<f64>()) {
+                    (Ok(i), _) => Value::Int(i),
+                    (_, Ok(x)) => Value::Float(x),
+                    _ => return Err(ExprError::new(pos, format!("bad number {:?}", text))),
+                };
+                toks.push((pos, Tok::Num(num)));
+                continue;
+            },
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = pos;
+                while let Some(&(i, d)) = chars.peek() {
+                    if !(d.is_alphanumeric() || d == '_') {
+                        break;
+                    };
+                    end = i + d.len_utf8();
+                    chars.next();
+                };
+                toks.push((pos, Tok::Ident(src[pos..end].to_string())));
+                continue;
+            },
+            c => return Err(ExprError::new(pos, format!("unexpected character {:?}", c))),
+        };
+
+        // consume the one or two characters of a punctuation token
+        let len = match tok {
+            Tok::Op(Op::And | Op::Or | Op::Eq | Op::Ne | Op::Le | Op::Ge) => 2,
+            _ => 1,
+        };
+        for _ in 0..len {
+            chars.next();
+        };
+        toks.push((pos, tok));
+    };
+
+    Ok(toks)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Lit(Value),
+    Var(String),
+    // a function of one query name: answer(), visited() or status()
+    Query(String, String),
+    Not(Box<Node>),
+    Neg(Box<Node>),
+    Bin(Op, Box<Node>, Box<Node>),
+}
+
+const FUNCTIONS: [&str; 3] = ["answer", "visited", "status"];
+
+struct Parser {
+    toks: Vec<(usize, Tok)>,
+    at: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.at).map(|(_, t)| t)
+    }
+
+    fn pos(&self) -> usize {
+        self.toks.get(self.at).map_or(self.end, |(p, _)| *p)
+    }
+
+    fn next(&mut self) -> Option<Tok> {
+        let tok = self.toks.get(self.at).map(|(_, t)| t.clone());
+        self.at += 1;
+        tok
+    }
+
+    fn expect(&mut self, want: Tok, what: &str) -> Result<(), ExprError> {
+        let pos = self.pos();
+        match self.next() {
+            Some(t) if t == want => Ok(()),
+            _ => Err(ExprError::new(pos, format!("expected {}", what))),
+        }
+    }
+
+    // The operator coming up next, if it is one of `ops`
+    fn op(&mut self, ops: &[Op]) -> Option<Op> {
+        match self.peek() {
+            Some(Tok::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.next();
+                Some(op)
+            },
+            _ => None,
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, ExprError> {
+        let mut lhs = self.and()?;
+        while let Some(op) = self.op(&[Op::Or]) {
+            lhs = Node::Bin(op, Box::new(lhs), Box::new(self.and()?));
+        };
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Node, ExprError> {
+        let mut lhs = self.comparison()?;
+        while let Some(op) = self.op(&[Op::And]) {
+            lhs = Node::Bin(op, Box::new(lhs), Box::new(self.comparison()?));
+        };
+        Ok(lhs)
+    }
+
+    // comparisons don't chain: `a < b < c` is an error
+    fn comparison(&mut self) -> Result<Node, ExprError> {
+        let lhs = self.sum()?;
+        match self.op(&[Op::Eq, Op::Ne, Op::Lt, Op::Le, Op::Gt, Op::Ge]) {
+            Some(op) => Ok(Node::Bin(op, Box::new(lhs), Box::new(self.sum()?))),
+            None => Ok(lhs),
+        }
+    }
+
+    fn sum(&mut self) -> Result<Node, ExprError> {
+        let mut lhs = self.product()?;
+        while let Some(op) = self.op(&[Op::Add, Op::Sub]) {
+            lhs = Node::Bin(op, Box::new(lhs), Box::new(self.product()?));
+        };
+        Ok(lhs)
+    }
+
+    fn product(&mut self) -> Result<Node, ExprError> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.op(&[Op::Mul, Op::Div, Op::Rem]) {
+            lhs = Node::Bin(op, Box::new(lhs), Box::new(self.unary()?));
+        };
+        Ok(lhs)
+    }
+
+    // `!` binds as tightly as unary `-`, so `!a == b` is `(!a) == b`
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        match self.peek() {
+            Some(Tok::Op(Op::Sub)) => {
+                self.next();
+                Ok(Node::Neg(Box::new(self.unary()?)))
+            },
+            Some(Tok::Not) => {
+                self.next();
+                Ok(Node::Not(Box::new(self.unary()?)))
+            },
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, ExprError> {
+        let pos = self.pos();
+        match self.next() {
+            Some(Tok::Str(s)) => Ok(Node::Lit(Value::Str(s))),
+            Some(Tok::Num(n)) => Ok(Node::Lit(n)),
+            Some(Tok::LParen) => {
+                let inner = self.or()?;
+                self.expect(Tok::RParen, "`)`")?;
+                Ok(inner)
+            },
+            Some(Tok::Ident(name)) if self.peek() == Some(&Tok::LParen) => {
+                if !FUNCTIONS.contains(&name.as_str()) {
+                    return Err(ExprError::new(pos, format!("unknown function `{}`", name)));
+                };
+                self.next();
+                let arg_pos = self.pos();
+                let query = match self.next() {
+                    Some(Tok::Ident(q)) | Some(Tok::Str(q)) => q,
+                    _ => return Err(ExprError::new(arg_pos, format!("`{}` takes a query label", name))),
+                };
+                self.expect(Tok::RParen, "`)`")?;
+                Ok(Node::Query(name, query))
+            },
+            Some(Tok::Ident(name)) => match name.as_str() {
+                "true" => Ok(Node::Lit(Value::Bool(true))),
+                "false" => Ok(Node::Lit(Value::Bool(false))),
+                _ => Ok(Node::Var(name)),
+            },
+            Some(_) => Err(ExprError::new(pos, "expected a value")),
+            None => Err(ExprError::new(pos, "unexpected end of expression")),
+        }
+    }
+}
+
+// A parsed expression, kept alongside its source so it can be written
+// back out as it was given.
+#[derive(Clone, Debug)]
+pub struct Expr {
+    src: String,
+    node: Node,
+}
+
+impl Expr {
+    pub fn parse(src: &str) -> Result<Expr, ExprError> {
+        let mut parser = Parser {
+            toks: tokenize(src)?,
+            at: 0,
+            end: src.len(),
+        };
+        let node = parser.or()?;
+        if parser.peek().is_some() {
+            return Err(ExprError::new(parser.pos(), "unexpected trailing input"));
+        };
+
+        Ok(Expr { src: src.to_string(), node })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.src
+    }
+
+    pub fn eval(&self, env: &dyn Env) -> Option<Value> {
+        eval(&self.node, env)
+    }
+
+    pub fn is_true(&self, env: &dyn Env) -> bool {
+        truthy(&self.eval(env))
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ExprError;
+
+    fn from_str(s: &str) -> Result<Self, ExprError> {
+        Expr::parse(s)
+    }
+}
+
+impl Serialize for Expr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.src)
+    }
+}
+
+impl<'de> Deserialize<'de> for Expr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Expr::parse(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+fn truthy(v: &Option<Value>) -> bool {
+    match v {
+        None => false,
+        Some(Value::Bool(b)) => *b,
+        Some(Value::Int(i)) => *i != 0,
+        Some(Value::Float(x)) => *x != 0.0,
+        Some(Value::Str(s)) => !s.is_empty(),
+    }
+}
+
+// Answers are text, so strings that look like numbers can be used as them
+fn number(v: &Value) -> Option<Value> {
+    match v {
+        Value::Int(_) | Value::Float(_) => Some(v.clone()),
+        Value::Str(s) => match s.trim().parse::<Value>() {
+            Ok(n @ (Value::Int(_) | Value::Float(_))) => Some(n),
+            _ => None,
+        },
+        Value::Bool(_) => None,
+    }
+}
+
+fn float(v: &Value) -> f64 {
+    match v {
+        Value::Int(i) => *i as f64,
+        Value::Float(x) => *x,
+        _ => f64::NAN,
+    }
+}
+
+// Numbers compare by value whatever their type, and anything compared with
+// a string is compared as text, so `answer(n) == 3` works for an answer "3".
+fn equal(a: &Option<Value>, b: &Option<Value>) -> bool {
+    match (a, b) {
+        (None, None) => true,
+        (None, _) | (_, None) => false,
+        (Some(Value::Int(i)), Some(Value::Float(x))) | (Some(Value::Float(x)), Some(Value::Int(i))) => {
+            *i as f64 == *x
+        },
+        (Some(a), Some(b)) if std::mem::discriminant(a) == std::mem::discriminant(b) => a == b,
+        (Some(a), Some(b)) => a.to_string() == b.to_string(),
+    }
+}
+
+// Orders two numbers (or number-like strings) by value, or two strings
+// alphabetically. Anything else has no order, so every `<` on it is false.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (number(a), number(b)) {
+        (Some(Value::Int(x)), Some(Value::Int(y))) => Some(x.cmp(&y)),
+        (Some(x), Some(y)) => float(&x).partial_cmp(&float(&y)),
+        _ => match (a, b) {
+            (Value::Str(x), Value::Str(y)) => Some(x.cmp(y)),
+            (Value::Bool(x), Value::Bool(y)) => Some(x.cmp(y)),
+            _ => None,
+        },
+    }
+}
+
+// Whole numbers stay whole unless a division doesn't come out even. `+`
+// joins text when either side isn't a number. Anything that doesn't make
+// sense (overflow, dividing by zero, `true * 2`) has no value.
+fn arithmetic(op: Op, a: &Value, b: &Value) -> Option<Value> {
+    let (x, y) = match (number(a), number(b)) {
+        (Some(x), Some(y)) => (x, y),
+        _ if op == Op::Add && (matches!(a, Value::Str(_)) || matches!(b, Value::Str(_))) => {
+            return Some(Value::Str(format!("{}{}", a, b)));
+        },
+        _ => return None,
+    };
+
+    if let (Value::Int(x), Value::Int(y)) = (&x, &y) {
+        let (x, y) = (*x, *y);
+        return match op {
+            Op::Add => x.checked_add(y).map(Value::Int),
+            Op::Sub => x.checked_sub(y).map(Value::Int),
+            Op::Mul => x.checked_mul(y).map(Value::Int),
+            Op::Rem => x.checked_rem(y).map(Value::Int),
+            // no remainder when dividing by zero, or for i64::MIN / -1
+            Op::Div => match x.checked_rem(y) {
+                Some(0) => x.checked_div(y).map(Value::Int),
+                Some(_) => Some(Value::Float(x as f64 / y as f64)),
+                None => None,
+            },
+            _ => None,
+        };
+    };
+
+    let (x, y) = (float(&x), float(&y));
+    let v = match op {
+        Op::Add => x + y,
+        Op::Sub => x - y,
+        Op::Mul => x * y,
+        Op::Div if y != 0.0 => x / y,
+        Op::Rem if y != 0.0 => x % y,
+        _ => return None,
+    };
+    Some(Value::Float(v))
+}
+
+fn eval(node: &Node, env: &dyn Env) -> Option<Value> {
+    let b = |v: bool| Some(Value::Bool(v));
+    match node {
+        Node::Lit(v) => Some(v.clone()),
+        Node::Var(name) => env.var(name),
+        Node::Query(f, q) if f == "answer" => env.answer(q),
+        Node::Query(f, q) if f == "status" => env.status(q),
+        Node::Query(_, q) => b(env.visited(q)),
+        Node::Not(n) => b(!truthy(&eval(n, env))),
+        Node::Neg(n) => match number(&eval(n, env)?)? {
+            Value::Int(i) => i.checked_neg().map(Value::Int),
+            x => Some(Value::Float(-float(&x))),
+        },
+        Node::Bin(Op::And, l, r) => b(truthy(&eval(l, env)) && truthy(&eval(r, env))),
+        Node::Bin(Op::Or, l, r) => b(truthy(&eval(l, env)) || truthy(&eval(r, env))),
+        Node::Bin(Op::Eq, l, r) => b(equal(&eval(l, env), &eval(r, env))),
+        Node::Bin(Op::Ne, l, r) => b(!equal(&eval(l, env), &eval(r, env))),
+        Node::Bin(op, l, r) => {
+            let (l, r) = (eval(l, env)?, eval(r, env)?);
+            match op {
+                Op::Lt => b(compare(&l, &r) == Some(Ordering::Less)),
+                Op::Le => b(matches!(compare(&l, &r), Some(Ordering::Less | Ordering::Equal))),
+                Op::Gt => b(compare(&l, &r) == Some(Ordering::Greater)),
+                Op::Ge => b(matches!(compare(&l, &r), Some(Ordering::Greater | Ordering::Equal))),
+                _ => arithmetic(*op, &l, &r),
+            }
+        },
+    }
+}
+
+// Outside of a session only the variables are known
+impl Env for Vars {
+    fn var(&self, name: &str) -> Option<Value> {
+        self.get(name).cloned()
+    }
+
+    fn answer(&self, _query: &str) -> Option<Value> {
+        None
+    }
+
+    fn visited(&self, _query: &str) -> bool {
+        false
+    }
+
+    fn status(&self, _query: &str) -> Option<Value> {
+        None
+    }
+}
+
+
+// A session where q1 was answered "yes" by running a command that exited
+// with 3, for tests here and in `template`
+#[cfg(test)]
+pub(crate) mod fixture {
+    use super::*;
+
+    pub struct TestEnv {
+        pub vars: Vars,
+        // stands in for the process environment
+        pub environment: Vec<(String, String)>,
+    }
+
+    impl Env for TestEnv {
+        fn var(&self, name: &str) -> Option<Value> {
+            self.vars.get(name).cloned()
+        }
+
+        fn answer(&self, query: &str) -> Option<Value> {
+            (query == "q1").then(|| Value::from("yes"))
+        }
+
+        fn visited(&self, query: &str) -> bool {
+            query == "q1"
+        }
+
+        fn status(&self, query: &str) -> Option<Value> {
+            (query == "q1").then_some(Value::Int(3))
+        }
+
+        fn env_var(&self, name: &str) -> Option<String> {
+            self.environment.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone())
+        }
+    }
+
+    pub fn env() -> TestEnv {
+        let mut vars = Vars::new();
+        vars.set("count", 4);
+        vars.set("name", "weid");
+        vars.set("off", false);
+        TestEnv { vars, environment: vec![("USER".to_string(), "ada".to_string())] }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::fixture::env;
+
+    fn check(src: &str) -> bool {
+        Expr::parse(src).unwrap().is_true(&env())
+    }
+
+    #[test]
+    fn guards() {
+        assert!(check("answer(q1) == \"yes\""));
+        assert!(check("answer('q1') != 'no' && visited(q1)"));
+        assert!(!check("visited(q2) || answer(q2) == 'yes'"));
+        assert!(check("!off && (name == 'weid' || off)"));
+        assert!(check("count == 4 && count == 4.0 && count != '5'"));
+        assert!(check("count"));
+        assert!(!check("missing"));
+        assert!(check("!missing && missing == missing"));
+    }
+
+    #[test]
+    fn arithmetic_and_comparison() {
+        let eval = |src: &str| Expr::parse(src).unwrap().eval(&env());
+
+        assert_eq!(eval("1 + 2 * 3"), Some(Value::Int(7)));
+        assert_eq!(eval("(1 + 2) * -3"), Some(Value::Int(-9)));
+        assert_eq!(eval("count / 2"), Some(Value::Int(2)));
+        assert_eq!(eval("count / 8"), Some(Value::Float(0.5)));
+        assert_eq!(eval("count % 3 + 0.5"), Some(Value::Float(1.5)));
+        assert_eq!(eval("answer(q1) + '!'"), Some(Value::from("yes!")));
+        assert_eq!(eval("status(q1) + 1"), Some(Value::Int(4)));
+        assert_eq!(eval("status(q2)"), None);
+        assert_eq!(eval("name + count"), Some(Value::from("weid4")));
+        assert_eq!(eval("'2' * count"), Some(Value::Int(8)));
+        assert_eq!(eval("count / 0"), None);
+        assert_eq!(eval("9223372036854775807 + 1"), None);
+        assert_eq!(eval("(0 - 9223372036854775807 - 1) / (0 - 1)"), None);
+        assert_eq!(eval("(0 - 9223372036854775807 - 1) % (0 - 1)"), None);
+        assert_eq!(eval("missing + 1"), None);
+        assert_eq!(eval("off * 2"), None);
+
+        assert!(check("answer(q1) == \"yes\" && count > 3"));
+        assert!(check("count >= 4 && count <= 4.0 && count < 10 && !(count > 4)"));
+        assert!(check("'10' > 9 && 'abc' < 'abd'"));
+        assert!(!check("missing < 1 || missing >= 1 || name > 3"));
+        assert!(check("!off == true"));
+        assert!(check("-count < 0"));
+
+        // `!` binds like unary `-`, tighter than comparisons
+        let parse = |src: &str| Expr::parse(src).unwrap();
+        assert_eq!(parse("!a == b"), parse("(!a) == b"));
+        assert_ne!(parse("!a == b"), parse("!(a == b)"));
+        assert_eq!(parse("!a < -b && !c"), parse("((!a) < (-b)) && (!c)"));
+
+        let vars = env().vars;
+        assert_eq!(Expr::parse("count * 10").unwrap().eval(&vars), Some(Value::Int(40)));
+        assert!(!Expr::parse("visited(q1)").unwrap().is_true(&vars));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = |src: &str| Expr::parse(src).unwrap_err();
+
+        assert_eq!(err("answer(q1) == ").pos(), 14);
+        assert_eq!(err("count = 3").pos(), 6);
+        assert_eq!(err("'open").pos(), 0);
+        assert_eq!(err("lookup(q1)").pos(), 0);
+        assert_eq!(err("(count == 3").pos(), 11);
+        assert_eq!(err("count 3").to_string(), "at position 6: unexpected trailing input");
+        assert_eq!(err("1 < 2 < 3").pos(), 6);
+        assert_eq!(err("count * / 2").pos(), 8);
+    }
+
+    #[test]
+    fn expr_serde() {
+        let e: Expr = serde_json::from_str(r#""visited(q1) && count == 4""#).unwrap();
+        assert_eq!(serde_json::to_string(&e).unwrap(), r#""visited(q1) && count == 4""#);
+        assert!(serde_json::from_str::<Expr>(r#""visited(""#).is_err());
+    }
+}
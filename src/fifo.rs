@@ -0,0 +1,161 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+use anyhow::{Context, Result, bail};
+
+use crate::querier::Querier;
+use crate::querylist::QueryList;
+use crate::script::{Script, ScriptFormat};
+
+pub fn is_fifo<P: AsRef<Path>>(path: P) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.file_type().is_fifo())
+}
+
+// Query definitions written to a named pipe while a session is running.
+// Each line is a JSON script (`{"queries": [...]}`, as for `-f`), or a
+// single query (`{"text": "retry?", "answers": [{"text": "yes"}]}`).
+//
+// The pipe is read on a thread of its own, since opening it blocks until
+// something opens it for writing. Writers can come and go.
+pub struct ControlChannel {
+    path: PathBuf,
+    rx: Receiver<String>,
+}
+
+impl ControlChannel {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<ControlChannel> {
+        let path = path.as_ref().to_path_buf();
+        if !is_fifo(&path) {
+            bail!("{}: not a named pipe (create one with mkfifo)", path.display());
+        };
+
+        let (tx, rx) = channel();
+        let reading = path.clone();
+        thread::spawn(move || -> Result<()> {
+            loop {
+                let fifo = File::open(&reading)?;
+                for line in BufReader::new(fifo).lines() {
+                    tx.send(line?)?;
+                };
+            };
+        });
+
+        Ok(ControlChannel { path, rx })
+    }
+
+    // Whatever has been written since last time, without waiting
+    pub fn pending(&self) -> Vec<String> {
+        self.rx.try_iter().collect()
+    }
+
+    // Waits for the next line. None if the pipe can no longer be read.
+    pub fn wait(&self) -> Option<String> {
+        self.rx.recv().ok()
+    }
+
+    // Parses a line into queries, see `parse`. Errors name the pipe.
+    pub fn parse<'a>(&self, line: &str) -> Result<QueryList<'a>> {
+        parse(line).with_context(|| format!("{}", self.path.display()))
+    }
+}
+
+pub fn parse<'a>(line: &str) -> Result<QueryList<'a>> {
+    let value: serde_json::Value = serde_json::from_str(line)?;
+    let text = if value.get("queries").is_some() {
+        line.to_string()
+    }
+    else {
+        serde_json::json!({ "queries": [value] }).to_string()
+    };
+    Ok(Script::parse(&text, ScriptFormat::Json)?.to_querylist())
+}
+
+// Whether a line asks a waiting session to end: `{"end": true}`
+fn is_end(line: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(line)
+        .is_ok_and(|v| v.get("end").and_then(|e| e.as_bool()) == Some(true))
+}
+
+// Runs a session that also takes queries from `channel`. New queries are
+// added to the end of the list, and show up once the query on screen has
+// been answered: a menu that is already open isn't changed.
+//
+// The session ends like any other when there is nothing left to ask,
+// unless `wait` is set. Then it waits for more queries, until a line
+// `{"end": true}` comes in or the pipe can't be read any more.
+pub fn run(querier: &mut Querier, channel: &ControlChannel, wait: bool) -> Result<()> {
+    let mut lines = Vec::new();
+    let mut ending = !wait;
+    loop {
+        lines.extend(channel.pending());
+        for line in lines.drain(..) {
+            if is_end(&line) {
+                ending = true;
+                continue;
+            };
+            match channel.parse(&line) {
+                Ok(ql) => querier.append_queries(ql),
+                Err(e) => eprintln!("{:#}", e),
+            };
+        };
+
+        if querier.step()? {
+            continue;
+        };
+        if querier.cancelled() || ending {
+            return Ok(());
+        };
+        match channel.wait() {
+            Some(line) => lines.push(line),
+            None => return Ok(()),
+        };
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use std::process::Command;
+
+    #[test]
+    fn messages() {
+        let ql = parse(r#"{"text": "retry?", "answers": [{"text": "yes"}, {"text": "no"}]}"#).unwrap();
+        assert_eq!(ql.get_query(0).unwrap().display(), "retry?");
+        assert_eq!(ql.get_query(0).unwrap().answers().len(), 2);
+
+        let ql = parse(r#"{"answers": [{"text": "ok"}], "queries": [{"text": "a"}, {"text": "b"}]}"#).unwrap();
+        assert_eq!(ql.order().len(), 2);
+        assert_eq!(ql.get_query(1).unwrap().answers()[0].display(), "ok");
+
+        assert!(parse("retry?").is_err());
+        assert!(is_end(r#"{"end": true}"#));
+        assert!(!is_end(r#"{"text": "end"}"#));
+        assert!(parse(r#"{"txt": "retry?"}"#).is_err());
+    }
+
+    #[test]
+    fn channel() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("weid.fifo");
+        assert!(ControlChannel::open(&path).is_err());
+        assert!(Command::new("mkfifo").arg(&path).status().unwrap().success());
+        assert!(is_fifo(&path));
+
+        let channel = ControlChannel::open(&path).unwrap();
+        for text in ["first", "second"] {
+            let mut w = fs::OpenOptions::new().write(true).open(&path).unwrap();
+            writeln!(w, r#"{{"text": "{}"}}"#, text).unwrap();
+        };
+
+        let first = channel.wait().unwrap();
+        assert_eq!(channel.parse(&first).unwrap().get_query(0).unwrap().display(), "first");
+        let second = channel.wait().unwrap();
+        assert!(second.contains("second"));
+        assert!(channel.pending().is_empty());
+    }
+}
@@ -0,0 +1,267 @@
+use std::fs::File;
+use std::io::{stdin, stdout, BufRead, BufReader, Write};
+use std::os::fd::{BorrowedFd, RawFd};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::prompt::{Menu, Pick, Prompter};
+use crate::querier::Querier;
+use crate::querylist::{AnswerId, QueryId};
+use crate::vars::Vars;
+
+// What weid says to the program driving it, one JSON object per line
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message<'m> {
+    // a query waiting for a reply
+    Query {
+        #[serde(flatten)]
+        query: QueryView<'m>,
+    },
+    // whatever an outcome printed
+    Output { text: &'m str },
+    // a reply that couldn't be used; the query is still waiting
+    Error { message: String },
+    // the session is over
+    End {
+        cancelled: bool,
+        answers: Vec<AnswerId>,
+        vars: &'m Vars,
+    },
+}
+
+// A query as it is sent to other programs
+#[derive(Debug, Serialize)]
+pub struct QueryView<'m> {
+    id: QueryId,
+    label: Option<&'m str>,
+    text: &'m str,
+    answers: Vec<MenuAnswer<'m>>,
+    can_go_back: bool,
+}
+
+impl<'m> QueryView<'m> {
+    pub fn new(menu: &'m Menu) -> Self {
+        QueryView {
+            id: menu.qid,
+            label: menu.label.as_deref(),
+            text: &menu.text,
+            answers: menu.answer_ids.iter().zip(&menu.answers).zip(&menu.values)
+                .map(|((id, text), value)| MenuAnswer { id, text, value: value.as_deref() })
+                .collect(),
+            can_go_back: menu.can_go_back,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct MenuAnswer<'m> {
+    id: &'m AnswerId,
+    text: &'m str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<&'m str>,
+}
+
+// What the driving program says back: `{"answer": 1}` picks by position
+// in the query's `answers`, `{"answer": "q0a1"}` by id, and `"back"` or
+// `"cancel"` do what they say
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Reply {
+    Answer { answer: Selection },
+    Word(String),
+}
+
+// An answer picked by its position in a menu, or by id
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Selection {
+    Index(usize),
+    Id(AnswerId),
+}
+
+impl Selection {
+    // The position in `menu.answers` of the answer picked
+    pub fn position(&self, menu: &Menu) -> Result<usize, String> {
+        match self {
+            Selection::Index(i) if *i < menu.answers.len() => Ok(*i),
+            Selection::Index(i) => Err(format!("there is no answer {}", i)),
+            Selection::Id(aid) => menu.answer_ids.iter()
+                .position(|a| a == aid)
+                .ok_or_else(|| format!("{:?} is not an answer to this query", aid)),
+        }
+    }
+}
+
+// Asks queries as JSON messages rather than on a terminal, so another
+// program can put its own front end on a session. Outcomes still run in
+// weid; what they print is passed on as `output` messages. End of input
+// cancels.
+pub struct JsonPrompter<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> JsonPrompter<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        JsonPrompter { input, output }
+    }
+
+    fn send(&mut self, message: &Message) -> Result<()> {
+        send(&mut self.output, message)
+    }
+
+    fn pick(menu: &Menu, line: &str) -> Result<Pick, String> {
+        let reply: Reply = serde_json::from_str(line).map_err(|_| {
+            format!("reply {} not understood: expected {{\"answer\": ...}}, \"back\" or \"cancel\"", line)
+        })?;
+        match reply {
+            Reply::Answer { answer } => answer.position(menu).map(Pick::Answer),
+            Reply::Word(w) if w == "back" && menu.can_go_back => Ok(Pick::Back),
+            Reply::Word(w) if w == "back" => Err("there is nothing to go back to".to_string()),
+            Reply::Word(w) if w == "cancel" => Ok(Pick::Cancel),
+            Reply::Word(w) => Err(format!("unknown reply {:?}", w)),
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Prompter for JsonPrompter<R, W> {
+    fn ask(&mut self, menu: &Menu) -> Result<Pick> {
+        self.send(&Message::Query { query: QueryView::new(menu) })?;
+
+        loop {
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(Pick::Cancel);
+            };
+            if line.trim().is_empty() {
+                continue;
+            };
+            match Self::pick(menu, line.trim()) {
+                Ok(pick) => return Ok(pick),
+                Err(message) => self.send(&Message::Error { message })?,
+            };
+        };
+    }
+
+    fn output(&mut self, text: &str) -> Result<()> {
+        self.send(&Message::Output { text })
+    }
+}
+
+pub fn send<W: Write>(output: &mut W, message: &Message) -> Result<()> {
+    serde_json::to_writer(&mut *output, message)?;
+    writeln!(output)?;
+    output.flush()?;
+    Ok(())
+}
+
+// Says how the session ended
+pub fn end<W: Write>(output: &mut W, querier: &Querier) -> Result<()> {
+    send(output, &Message::End {
+        cancelled: querier.cancelled(),
+        answers: querier.answered(),
+        vars: querier.vars(),
+    })
+}
+
+// stdin, or a copy of an fd handed down by the driving program
+pub fn reader(fd: Option<RawFd>) -> Result<Box<dyn BufRead>> {
+    match fd {
+        Some(fd) => Ok(Box::new(BufReader::new(open_fd(fd)?))),
+        None => Ok(Box::new(stdin().lock())),
+    }
+}
+
+// stdout, or a copy of an fd handed down by the driving program
+pub fn writer(fd: Option<RawFd>) -> Result<Box<dyn Write>> {
+    match fd {
+        Some(fd) => Ok(Box::new(open_fd(fd)?)),
+        None => Ok(Box::new(stdout())),
+    }
+}
+
+fn open_fd(fd: RawFd) -> Result<File> {
+    // SAFETY: the borrow only lasts long enough to duplicate the fd, which
+    // fails cleanly if it isn't open
+    let owned = unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()
+        .with_context(|| format!("--headless: fd {} is not open", fd))?;
+    Ok(File::from(owned))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::qa::*;
+    use crate::querylist::QueryList;
+
+    fn menu() -> Menu {
+        Menu {
+            qid: 2,
+            label: Some("go".to_string()),
+            text: "Continue?".to_string(),
+            answers: vec!["yes".to_string(), "no".to_string()],
+            answer_ids: vec![AnswerId::new(2, 0), AnswerId::new(2, 1)],
+            values: vec![Some("continue".to_string()), None],
+            can_go_back: false,
+        }
+    }
+
+    fn ask(replies: &str) -> (Pick, Vec<serde_json::Value>) {
+        let mut out = Vec::new();
+        let pick = JsonPrompter::new(replies.as_bytes(), &mut out).ask(&menu()).unwrap();
+        let messages = String::from_utf8(out).unwrap().lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        (pick, messages)
+    }
+
+    #[test]
+    fn json_prompter() {
+        let (pick, messages) = ask("{\"answer\": 1}\n");
+        assert_eq!(pick, Pick::Answer(1));
+        assert_eq!(messages, [serde_json::json!({
+            "type": "query",
+            "id": 2,
+            "label": "go",
+            "text": "Continue?",
+            "answers": [{"id": "q2a0", "text": "yes", "value": "continue"}, {"id": "q2a1", "text": "no"}],
+            "can_go_back": false,
+        })]);
+
+        assert_eq!(ask("{\"answer\": \"q2a0\"}\n").0, Pick::Answer(0));
+        assert_eq!(ask("\"cancel\"\n").0, Pick::Cancel);
+        assert_eq!(ask("").0, Pick::Cancel);
+
+        // bad replies are answered with an error, and the query asked again
+        let (pick, messages) = ask("2\n{\"answer\": 2}\n{\"answer\": \"q0a0\"}\n\"back\"\n\n{\"answer\": 0}\n");
+        assert_eq!(pick, Pick::Answer(0));
+        let errors: Vec<_> = messages[1..].iter().map(|m| m["type"].as_str().unwrap()).collect();
+        assert_eq!(errors, ["error"; 4]);
+        assert!(messages[1]["message"].as_str().unwrap().starts_with("reply 2 not understood"));
+        assert_eq!(messages[2]["message"], "there is no answer 2");
+        assert_eq!(messages[3]["message"], "q0a0 is not an answer to this query");
+        assert_eq!(messages[4]["message"], "there is nothing to go back to");
+
+        let mut out = Vec::new();
+        JsonPrompter::new("".as_bytes(), &mut out).output("going\n").unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "{\"type\":\"output\",\"text\":\"going\\n\"}\n");
+    }
+
+    #[test]
+    fn end_message() {
+        let mut q = Query::from_text("Continue?".to_string());
+        q.add_answer(Answer::from_text("yes".to_string()));
+        let mut ql = QueryList::new();
+        ql.insert_query(q);
+        let mut querier = Querier::new(ql);
+        querier.vars_mut().set("n", 3);
+
+        let mut out = Vec::new();
+        end(&mut out, &querier).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+            "{\"type\":\"end\",\"cancelled\":false,\"answers\":[],\"vars\":{\"n\":3}}\n");
+    }
+}
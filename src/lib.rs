@@ -1,7 +1,22 @@
+pub mod batch;
+pub mod dmenu;
+pub mod expr;
+pub mod fifo;
+pub mod headless;
+pub mod order;
 pub mod outcome;
+pub mod prompt;
 pub mod qa;
 pub mod querylist;
 pub mod querier;
+pub mod records;
+pub mod report;
+pub mod script;
+pub mod server;
+pub mod shell;
+pub mod template;
+pub mod transcript;
+pub mod vars;
 
 pub mod cli;
 
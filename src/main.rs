@@ -8,7 +8,7 @@
 use std::env;
 use std::string::String;
 use std::collections::HashMap;
-use std::io::{stdout, stderr, Write, Read};
+use std::io::{stdin, stdout, stderr, Write, Read};
 use std::process::Command;
 use std::fs::File;
 
@@ -17,12 +17,20 @@ use termimad as t;
 use nanoid::nanoid;
 use anyhow::{Context, Result};
 use tempfile::tempdir;
-use open;
 
 use weid::qa::*;
 use weid::querylist::*;
 use weid::querier::*;
 use weid::cli;
+use weid::script;
+use weid::dmenu;
+use weid::batch;
+use weid::prompt;
+use weid::fifo;
+use weid::headless;
+use weid::server;
+use weid::transcript;
+use weid::report;
 
 fn edit_in_editor(start_text: &String) -> Result<String> {
     let editor = env::var("EDITOR").context("no EDITOR defined")?;
@@ -52,31 +60,119 @@ fn do_output(outs: Vec<&Answer>) -> Result<()> {
 
 fn output_query_results(anss: Vec<String>) {
     for ans in anss.iter() {
-        stdout().write_all(&ans.as_bytes()).unwrap();
+        stdout().write_all(ans.as_bytes()).unwrap();
         stdout().write_all("\n".as_bytes()).unwrap();
     };
 }
 
-fn do_weid() -> Result<()> {
-    let ql = cli::get_arg_queries().unwrap();
+// Builds a single query from the lines on stdin and prints the chosen
+// line. Keys are read from the terminal, since stdin is taken up by the
+// items, and the menu is kept off stdout unless that is the terminal.
+// Returns the exit code.
+fn do_dmenu(opts: &cli::Options) -> Result<i32> {
+    let items = dmenu::read_items(stdin().lock())?;
+    let ql = dmenu::to_querylist(opts.prompt.as_deref().unwrap_or(""), items);
+
+    let tty = prompt::open_tty().context("dmenu mode needs a terminal to read keys from")?;
+    let mut prompter = dmenu::DmenuPrompter::new(tty, prompt::terminal_output());
+    prompter.set_ignore_case(opts.ignore_case);
+    prompter.set_lines(opts.lines);
 
     let mut querier = Querier::new(ql);
+    querier.set_prompter(Box::new(prompter));
+    querier.run()?;
 
-    while let Some(qid) = querier.pick_next_query() {
-        querier.mark_visited(qid);
-        let query = querier.get_query(qid.clone()).unwrap();
-        let answer = querier.execute_query(&query)?;
-        for o in answer.outcomes() {
-            let out = o.execute()?;
-            stdout().write_all(out.as_bytes())?;
+    // like dmenu, cancelling prints nothing and fails
+    if querier.cancelled() {
+        return Ok(1);
+    };
+    for aid in querier.answered() {
+        if let Some(answer) = querier.get_query(aid.qid()).and_then(|q| q.answers().get(aid.sub()).cloned()) {
+            writeln!(stdout(), "{}", answer.value())?;
         };
     };
 
-    Ok(())
+    Ok(0)
+}
+
+// Runs a session as the args say, and returns the exit code
+fn do_weid() -> Result<i32> {
+    let opts = cli::get_arg_options()?;
+    if opts.dmenu {
+        return do_dmenu(&opts);
+    };
+    let ql = if opts.each {
+        let items = dmenu::read_items(stdin().lock())?;
+        cli::get_arg_queries_for_items(&items)?
+    }
+    else {
+        cli::get_arg_queries()?
+    };
+
+    if let Some(path) = &opts.dump_script {
+        script::dump(&ql, opts.fallback, path)?;
+        return Ok(0);
+    };
+
+    let mut querier = Querier::with_strategy(ql, opts.order.strategy(opts.seed, opts.count));
+    querier.set_fallback(opts.fallback);
+    // a mistyped --exit-from is better caught before the session than after
+    querier.exit_code(opts.exit_from.as_deref())?;
+    let (reply_fd, message_fd) = opts.headless_fds.unzip();
+    // at most one of these is given, see cli::_to_options
+    let mut prompter: Option<Box<dyn prompt::Prompter>> = None;
+    if opts.headless {
+        prompter = Some(Box::new(headless::JsonPrompter::new(headless::reader(reply_fd)?, headless::writer(message_fd)?)));
+    }
+    else if let Some(spec) = &opts.answers {
+        prompter = Some(Box::new(batch::BatchPrompter::new(batch::load(spec)?)));
+    }
+    else if let Some(path) = &opts.replay {
+        prompter = Some(Box::new(transcript::Replayer::new(transcript::load(path)?)));
+    };
+    // stdout is for the report, so outcome output goes elsewhere
+    if let Some(format) = opts.output {
+        let inner = prompter.unwrap_or_else(|| Box::new(prompt::TermPrompter::terminal()));
+        prompter = Some(Box::new(report::Diverted::new(inner, format, stdout())));
+    };
+    // whichever way queries are answered, the recording sits in between
+    if let Some(path) = &opts.record {
+        let inner = prompter.unwrap_or_else(|| Box::new(prompt::TermPrompter::terminal()));
+        prompter = Some(Box::new(transcript::Recorder::create(inner, path)?));
+    };
+    if let Some(prompter) = prompter {
+        querier.set_prompter(prompter);
+    };
+
+    let result = match (&opts.serve, &opts.fifo) {
+        (Some(address), _) => server::serve(&mut querier, address),
+        (_, Some(path)) => fifo::ControlChannel::open(path).and_then(|c| fifo::run(&mut querier, &c, opts.wait)),
+        _ => querier.run(),
+    };
+    if let Some(format) = opts.output {
+        report::write(&mut stdout(), format, &querier, result.as_ref().err())?;
+    };
+    result?;
+    if opts.headless {
+        headless::end(&mut headless::writer(message_fd)?, &querier)?;
+    };
+
+    if let Some(path) = &opts.dump_vars {
+        querier.vars().dump(path)?;
+    };
+
+    querier.exit_code(opts.exit_from.as_deref())
 }
 
 
 fn main() {
     env_logger::init();
-    do_weid().unwrap();
+    let code = match do_weid() {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("weid: {:#}", e);
+            EXIT_ERROR
+        },
+    };
+    std::process::exit(code);
 }
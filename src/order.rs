@@ -0,0 +1,352 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+use crate::querylist::*;
+
+// Decides which query is asked next when the last answer didn't have a
+// path attached to it. Only `candidates`, the queries whose guards
+// currently hold (in list order), may be picked. `last` is the query that
+// was just answered, if any.
+pub trait OrderingStrategy {
+    fn pick(
+        &mut self,
+        ql: &QueryList,
+        candidates: &[QueryId],
+        visited: &[QueryId],
+        last: Option<QueryId>,
+    ) -> Option<QueryId>;
+}
+
+// The built-in strategies, by the names used for `--order`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Order {
+    #[default]
+    Sequential,
+    Shuffle,
+    Random,
+    Weighted,
+    Priority,
+}
+
+impl FromStr for Order {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sequential" => Ok(Order::Sequential),
+            "shuffle" => Ok(Order::Shuffle),
+            "random" => Ok(Order::Random),
+            "weighted" => Ok(Order::Weighted),
+            "priority" => Ok(Order::Priority),
+            _ => bail!(
+                "Unknown order {:?} (expected sequential, shuffle, random, weighted or priority)", s
+            ),
+        }
+    }
+}
+
+impl Order {
+    // A `seed` makes the random strategies repeatable from run to run.
+    // `count` is how many queries `Random` asks before it stops; without
+    // one it goes on for as long as the session does.
+    pub fn strategy<'s>(&self, seed: Option<u64>, count: Option<usize>) -> Box<dyn OrderingStrategy + 's> {
+        match self {
+            Order::Sequential => Box::new(Sequential::default()),
+            Order::Shuffle => Box::new(Shuffled::new(seeded_rng(seed))),
+            Order::Random => Box::new(RandomWithReplacement::new(seeded_rng(seed), count)),
+            Order::Weighted => Box::new(Weighted::new(seeded_rng(seed))),
+            Order::Priority => Box::new(Priority),
+        }
+    }
+}
+
+pub fn seeded_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(s) => StdRng::seed_from_u64(s),
+        None => StdRng::from_entropy(),
+    }
+}
+
+fn unvisited<'q>(candidates: &'q [QueryId], visited: &'q [QueryId]) -> impl Iterator<Item = QueryId> + 'q {
+    candidates.iter().copied().filter(move |qid| !visited.contains(qid))
+}
+
+// Insertion order, carrying on after whichever query was answered last so
+// queries that were jumped over stay skipped. Queries added since the
+// previous pick are asked first, wherever they went in, and the order then
+// carries on from where it was before them.
+#[derive(Clone, Debug, Default)]
+pub struct Sequential {
+    known: Option<HashSet<QueryId>>,
+    inserted: HashSet<QueryId>,
+    resume_after: Option<QueryId>,
+}
+
+impl OrderingStrategy for Sequential {
+    fn pick(
+        &mut self,
+        ql: &QueryList,
+        candidates: &[QueryId],
+        visited: &[QueryId],
+        last: Option<QueryId>,
+    ) -> Option<QueryId> {
+        let order = ql.order();
+        let askable = |qid: &QueryId| candidates.contains(qid) && !visited.contains(qid);
+
+        match last {
+            Some(l) if !self.inserted.contains(&l) => self.resume_after = Some(l),
+            Some(_) => (),
+            None => self.resume_after = None,
+        };
+        let known = self.known.replace(order.iter().copied().collect());
+        if let Some(known) = known {
+            let new = order.iter().copied().find(|qid| !known.contains(qid) && askable(qid));
+            if let Some(qid) = new {
+                self.inserted.insert(qid);
+                return Some(qid);
+            };
+        };
+
+        let start = self.resume_after
+            .and_then(|l| order.iter().position(|qid| *qid == l))
+            .map_or(0, |pos| pos + 1);
+
+        order[start..].iter().copied().find(askable)
+    }
+}
+
+// Every query once, in a random order. Queries added mid-session are
+// shuffled into the ones not yet asked.
+#[derive(Clone, Debug)]
+pub struct Shuffled<R: Rng> {
+    rng: R,
+    queue: Vec<QueryId>,
+    seen: HashSet<QueryId>,
+}
+
+impl<R: Rng> Shuffled<R> {
+    pub fn new(rng: R) -> Self {
+        Shuffled {
+            rng,
+            queue: Vec::new(),
+            seen: HashSet::new(),
+        }
+    }
+}
+
+impl<R: Rng> OrderingStrategy for Shuffled<R> {
+    fn pick(
+        &mut self,
+        ql: &QueryList,
+        candidates: &[QueryId],
+        visited: &[QueryId],
+        _last: Option<QueryId>,
+    ) -> Option<QueryId> {
+        let mut new: Vec<QueryId> = ql.order().iter().copied()
+            .filter(|qid| self.seen.insert(*qid))
+            .collect();
+        new.shuffle(&mut self.rng);
+        for qid in new {
+            let at = self.rng.gen_range(0..=self.queue.len());
+            self.queue.insert(at, qid);
+        };
+
+        self.queue.retain(|qid| !visited.contains(qid) && ql.get_query(*qid).is_some());
+        self.queue.iter().copied().find(|qid| candidates.contains(qid))
+    }
+}
+
+// Any query at random, whether it has been asked before or not, until
+// `limit` queries have been asked. Without a limit, the session only ends
+// if the QueryList is emptied.
+#[derive(Clone, Debug)]
+pub struct RandomWithReplacement<R: Rng> {
+    rng: R,
+    limit: Option<usize>,
+}
+
+impl<R: Rng> RandomWithReplacement<R> {
+    pub fn new(rng: R, limit: Option<usize>) -> Self {
+        RandomWithReplacement { rng, limit }
+    }
+}
+
+impl<R: Rng> OrderingStrategy for RandomWithReplacement<R> {
+    fn pick(
+        &mut self,
+        _ql: &QueryList,
+        candidates: &[QueryId],
+        visited: &[QueryId],
+        _last: Option<QueryId>,
+    ) -> Option<QueryId> {
+        if self.limit.is_some_and(|n| visited.len() >= n) {
+            return None;
+        };
+        candidates.choose(&mut self.rng).copied()
+    }
+}
+
+// Unasked queries at random, in proportion to `Query::weight`. Queries
+// with a weight of zero are never picked.
+#[derive(Clone, Debug)]
+pub struct Weighted<R: Rng> {
+    rng: R,
+}
+
+impl<R: Rng> Weighted<R> {
+    pub fn new(rng: R) -> Self {
+        Weighted { rng }
+    }
+}
+
+impl<R: Rng> OrderingStrategy for Weighted<R> {
+    fn pick(
+        &mut self,
+        ql: &QueryList,
+        candidates: &[QueryId],
+        visited: &[QueryId],
+        _last: Option<QueryId>,
+    ) -> Option<QueryId> {
+        let weight = |qid: &QueryId| ql.get_query(*qid).map_or(0.0, |q| q.weight().max(0.0));
+        let choices: Vec<QueryId> = unvisited(candidates, visited).filter(|qid| weight(qid) > 0.0).collect();
+        if choices.is_empty() {
+            return None;
+        };
+
+        // weights are finite and positive (see `Query::set_weight`), so this
+        // only fails if together they are too big for an f64
+        match choices.choose_weighted(&mut self.rng, weight) {
+            Ok(qid) => Some(*qid),
+            Err(_) => choices.choose(&mut self.rng).copied(),
+        }
+    }
+}
+
+// Unasked queries from highest `Query::priority` to lowest, in insertion
+// order among equals.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Priority;
+
+impl OrderingStrategy for Priority {
+    fn pick(
+        &mut self,
+        ql: &QueryList,
+        candidates: &[QueryId],
+        visited: &[QueryId],
+        _last: Option<QueryId>,
+    ) -> Option<QueryId> {
+        let priority = |qid: &QueryId| ql.get_query(*qid).map_or(i64::MIN, |q| q.priority());
+
+        // max_by_key keeps the last of equal elements, so walk backwards
+        unvisited(candidates, visited).collect::<Vec<_>>().into_iter().rev().max_by_key(priority)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::qa::*;
+
+    fn gen_querylist<'a>(n: usize) -> QueryList<'a> {
+        let mut ql = QueryList::new();
+        for i in 0..n {
+            ql.insert_query(Query::from_text(format!("q{}", i)));
+        };
+        ql
+    }
+
+    // asks everything the strategy gives us, up to `limit` queries
+    fn run(strategy: &mut dyn OrderingStrategy, ql: &QueryList, limit: usize) -> Vec<QueryId> {
+        let mut visited = Vec::new();
+        let mut last = None;
+        while let Some(qid) = strategy.pick(ql, ql.order(), &visited, last) {
+            visited.push(qid);
+            last = Some(qid);
+            if visited.len() == limit {
+                break;
+            };
+        };
+        visited
+    }
+
+    #[test]
+    fn sequential() {
+        let ql = gen_querylist(4);
+        assert_eq!(run(&mut Sequential::default(), &ql, 10), [0, 1, 2, 3]);
+        assert_eq!(Sequential::default().pick(&ql, ql.order(), &[0, 2], Some(2)), Some(3));
+        assert_eq!(Sequential::default().pick(&ql, ql.order(), &[0, 2], None), Some(1));
+        assert_eq!(Sequential::default().pick(&ql, &[0, 3], &[0], None), Some(3));
+    }
+
+    #[test]
+    fn shuffled() {
+        let ql = gen_querylist(20);
+
+        let first = run(&mut Shuffled::new(seeded_rng(Some(7))), &ql, 100);
+        let again = run(&mut Shuffled::new(seeded_rng(Some(7))), &ql, 100);
+        assert_eq!(first, again);
+
+        let mut sorted = first.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..20).collect::<Vec<_>>());
+        assert_ne!(first, sorted);
+    }
+
+    #[test]
+    fn random_with_replacement() {
+        let ql = gen_querylist(3);
+        let picks = run(&mut RandomWithReplacement::new(seeded_rng(Some(1)), None), &ql, 50);
+        assert_eq!(picks.len(), 50);
+        assert!(picks.iter().all(|qid| *qid < 3));
+
+        let mut strategy = RandomWithReplacement::new(seeded_rng(Some(1)), None);
+        assert_eq!(strategy.pick(&ql, &[1], &[1], None), Some(1));
+        assert_eq!(strategy.pick(&ql, &[], &[], None), None);
+
+        // with a limit, it stops by itself
+        assert_eq!(run(&mut RandomWithReplacement::new(seeded_rng(Some(1)), Some(7)), &ql, 50).len(), 7);
+    }
+
+    #[test]
+    fn weighted() {
+        let mut ql = QueryList::new();
+        let mut never = Query::from_text("never".to_string());
+        never.set_weight(0.0).unwrap();
+        let never = ql.insert_query(never);
+        let mut heavy = Query::from_text("heavy".to_string());
+        heavy.set_weight(1000.0).unwrap();
+        for bad in [f64::INFINITY, f64::NAN, -1.0] {
+            assert!(heavy.set_weight(bad).is_err());
+        };
+        let heavy = ql.insert_query(heavy);
+        let light = ql.insert_query(Query::from_text("light".to_string()));
+
+        let picks = run(&mut Weighted::new(seeded_rng(Some(3))), &ql, 10);
+        assert_eq!(picks, [heavy, light]);
+        assert!(!picks.contains(&never));
+    }
+
+    #[test]
+    fn priority() {
+        let mut ql = QueryList::new();
+        let low = ql.insert_query(Query::from_text("low".to_string()));
+        let mut q = Query::from_text("high".to_string());
+        q.set_priority(5);
+        let high = ql.insert_query(q);
+        let low2 = ql.insert_query(Query::from_text("low2".to_string()));
+
+        assert_eq!(run(&mut Priority, &ql, 10), [high, low, low2]);
+    }
+
+    #[test]
+    fn order_names() {
+        assert_eq!("shuffle".parse::<Order>().unwrap(), Order::Shuffle);
+        assert!("alphabetical".parse::<Order>().is_err());
+    }
+}
@@ -1,62 +1,351 @@
 #![allow(unused_variables)]
 
-use std::process::Command;
 use std::rc::Rc;
 use std::fmt;
+use std::process::Output;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize, Serializer};
+use serde::ser::Error;
 
 use crate::querylist::*;
+use crate::vars::*;
+use crate::expr::{Env, Expr};
+use crate::records::Fill;
+use crate::shell;
 
+type ModifyFn<'a> = dyn Fn(&mut QueryList<'a>) -> Result<()> + 'a;
+type ClosureFn<'a> = dyn Fn(&mut Vars) -> Result<String> + 'a;
 
-
-#[derive(Clone)]
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Outcome<'a> {
-    Modify(Rc<dyn FnOnce(&mut QueryList) -> Result<()> + 'a>),
+    #[serde(skip)]
+    Modify(Rc<ModifyFn<'a>>),
     Command(String),
-    Closure(Rc<dyn Fn() -> Result<String> + 'a>),
+    #[serde(skip)]
+    Closure(Rc<ClosureFn<'a>>),
+    // `run`, plus a compensating `undo` for when the user backs out of
+    // the answer it belongs to
+    Undoable {
+        run: Box<Outcome<'a>>,
+        undo: Box<Outcome<'a>>,
+    },
+    // sets a session variable
+    SetVar {
+        name: String,
+        value: Value,
+    },
+    // sets a session variable to the value of an expression
+    Compute {
+        name: String,
+        expr: Expr,
+    },
+    // a command line run with `sh -c`, for pipes, redirects and the like.
+    // `Command` runs the program directly.
+    Shell(String),
+}
+
+// What running an outcome did. Only commands have an exit status and
+// stderr; for everything else there is just what it returned as output.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct OutcomeResult {
+    pub stdout: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub stderr: String,
+    // None for outcomes that aren't commands, and for commands that were
+    // killed by a signal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<i32>,
+    #[serde(serialize_with = "seconds")]
+    pub duration: Duration,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<OutcomeError>,
+}
+
+fn seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+impl OutcomeResult {
+    pub fn new(stdout: String) -> Self {
+        OutcomeResult { stdout, ..Default::default() }
+    }
+
+    // A command that ran. One that exited with a non-zero status has an
+    // error, but doesn't end the session.
+    pub fn from_output(out: Output) -> Self {
+        let error = match out.status.code() {
+            _ if out.status.success() => None,
+            Some(code) => Some(OutcomeError::new(ErrorKind::Status, format!("exited with status {}", code))),
+            None => Some(OutcomeError::new(ErrorKind::Status, "killed by a signal")),
+        };
+        OutcomeResult {
+            stdout: String::from_utf8_lossy(&out.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&out.stderr).into_owned(),
+            status: out.status.code(),
+            duration: Duration::ZERO,
+            error,
+        }
+    }
+
+    // Like `from_output`, for a command run with `sh -c`: status 127 is
+    // the shell's way of saying it couldn't find a command
+    pub fn from_sh_output(out: Output) -> Self {
+        let mut result = OutcomeResult::from_output(out);
+        if result.status == Some(127) {
+            result.error = Some(OutcomeError::new(ErrorKind::NotFound, "exited with status 127 (command not found)"));
+        };
+        result
+    }
+
+    // An outcome that returned an error, which ended the session
+    pub fn failed(error: &anyhow::Error) -> Self {
+        OutcomeResult { error: Some(OutcomeError::of(error)), ..Default::default() }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    // the command line was empty, or couldn't be split into words
+    BadCommand,
+    // there is no such program
+    NotFound,
+    // the program couldn't be started for some other reason
+    Spawn,
+    // the program ran, but exited with a non-zero status or was killed
+    Status,
+    // anything else, like a closure or an expression failing
+    Other,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OutcomeError {
+    pub kind: ErrorKind,
+    pub message: String,
 }
 
+impl OutcomeError {
+    pub fn new<S: Into<String>>(kind: ErrorKind, message: S) -> Self {
+        OutcomeError { kind, message: message.into() }
+    }
+
+    // Classifies an error from running an outcome. Errors that weren't
+    // made as an OutcomeError, anywhere in the chain, are `Other`.
+    pub fn of(error: &anyhow::Error) -> Self {
+        let kind = error.chain()
+            .find_map(|e| e.downcast_ref::<OutcomeError>())
+            .map_or(ErrorKind::Other, |e| e.kind);
+        OutcomeError::new(kind, format!("{:#}", error))
+    }
+}
+
+impl fmt::Display for OutcomeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for OutcomeError {}
+
 impl<'a> fmt::Debug for Outcome<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Outcome")
     }
 }
 
+// Only data-only variants can be written out; closures can't be, and
+// silently dropping them would produce a script that does something else.
+impl<'a> Serialize for Outcome<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Outcome::Command(cmd) => {
+                serializer.serialize_newtype_variant("Outcome", 1, "command", cmd)
+            },
+            Outcome::Modify(_) => Err(S::Error::custom("Modify outcomes are not serializable")),
+            Outcome::Closure(_) => Err(S::Error::custom("Closure outcomes are not serializable")),
+            // written as a newtype around a struct, since the toml
+            // serializer doesn't do struct variants. It reads back the same.
+            Outcome::Undoable { run, undo } => {
+                #[derive(Serialize)]
+                struct Undoable<'o, 'a> {
+                    run: &'o Outcome<'a>,
+                    undo: &'o Outcome<'a>,
+                }
+                serializer.serialize_newtype_variant("Outcome", 3, "undoable", &Undoable { run, undo })
+            },
+            Outcome::SetVar { name, value } => {
+                #[derive(Serialize)]
+                struct SetVar<'o> {
+                    name: &'o String,
+                    value: &'o Value,
+                }
+                serializer.serialize_newtype_variant("Outcome", 4, "set_var", &SetVar { name, value })
+            },
+            Outcome::Compute { name, expr } => {
+                #[derive(Serialize)]
+                struct Compute<'o> {
+                    name: &'o String,
+                    expr: &'o Expr,
+                }
+                serializer.serialize_newtype_variant("Outcome", 5, "compute", &Compute { name, expr })
+            },
+            Outcome::Shell(cmd) => {
+                serializer.serialize_newtype_variant("Outcome", 6, "shell", cmd)
+            },
+        }
+    }
+}
+
 impl<'a> Outcome<'a> {
     pub fn new_cmd(cmd: String) -> Self {
         Outcome::Command(cmd)
     }
 
+    pub fn new_shell(cmd: String) -> Self {
+        Outcome::Shell(cmd)
+    }
+
     pub fn new_closure<F>(fun: F) -> Self
     where 
         F: Fn() -> Result<String> + 'a
     {
-        Outcome::Closure(Rc::new(fun))
+        Outcome::Closure(Rc::new(move |_: &mut Vars| fun()))
         
     }
 
-    pub fn execute(&self) -> Result<String> {
+    // A closure that can read and write the session variables
+    pub fn new_closure_with_vars<F>(fun: F) -> Self
+    where
+        F: Fn(&mut Vars) -> Result<String> + 'a
+    {
+        Outcome::Closure(Rc::new(fun))
+    }
+
+    pub fn new_set_var<V: Into<Value>>(name: &str, value: V) -> Self {
+        Outcome::SetVar {
+            name: name.to_string(),
+            value: value.into(),
+        }
+    }
+
+    pub fn new_compute(name: &str, expr: Expr) -> Self {
+        Outcome::Compute {
+            name: name.to_string(),
+            expr,
+        }
+    }
+
+    pub fn new_modify<F>(fun: F) -> Self
+    where
+        F: Fn(&mut QueryList<'a>) -> Result<()> + 'a
+    {
+        Outcome::Modify(Rc::new(fun))
+    }
+
+    pub fn with_undo(self, undo: Outcome<'a>) -> Self {
+        Outcome::Undoable {
+            run: Box::new(self),
+            undo: Box::new(undo),
+        }
+    }
+
+    // A copy with `fill` applied to commands and text variables, which is
+    // how `{}` and `{field}` placeholders get replaced for --each and
+    // --records. In commands, each item is quoted to stay one word.
+    pub fn fill(&self, fill: &dyn Fill) -> Self {
         match self {
-            Outcome::Modify(_) => todo!(),
-            Outcome::Closure(f) => {
-                f()
+            Outcome::Command(cmd) => Outcome::Command(fill.command(cmd)),
+            Outcome::Shell(cmd) => Outcome::Shell(fill.command(cmd)),
+            Outcome::Undoable { run, undo } => run.fill(fill).with_undo(undo.fill(fill)),
+            Outcome::SetVar { name, value: Value::Str(s) } => Outcome::new_set_var(name, fill.text(s)),
+            _ => self.clone(),
+        }
+    }
 
-            },
+    // Whether running this starts another program, now or when undone
+    pub fn runs_program(&self) -> bool {
+        match self {
+            Outcome::Command(_) | Outcome::Shell(_) => true,
+            Outcome::Undoable { run, undo } => run.runs_program() || undo.runs_program(),
+            _ => false,
+        }
+    }
+
+    // The compensating action to run if this outcome is backed out of
+    pub fn undo(&self) -> Option<Outcome<'a>> {
+        match self {
+            Outcome::Undoable { undo, .. } => Some(*undo.clone()),
+            _ => None,
+        }
+    }
+
+    // Outside of a session there is no QueryList or variable store, so
+    // outcomes that act on the session, closures included, are an error
+    // rather than quietly losing what they do. Use `execute_on` (usually by
+    // way of `Querier::execute_outcome`) to run them for real.
+    pub fn execute(&self) -> Result<OutcomeResult> {
+        let start = Instant::now();
+        let mut result = match self {
+            Outcome::Modify(_) => bail!("Modify outcomes must be executed with a QueryList"),
+            Outcome::SetVar { .. } => bail!("SetVar outcomes must be executed with session variables"),
+            Outcome::Compute { .. } => bail!("Compute outcomes must be executed with session variables"),
+            Outcome::Closure(_) => bail!("Closure outcomes must be executed with session variables"),
             Outcome::Command(cmd) => {
-                run_external_cmd(cmd.clone())
+                run_external_cmd(cmd.clone())?
             },
-        }
+            Outcome::Shell(cmd) => {
+                OutcomeResult::from_sh_output(shell::run_sh(cmd)?)
+            },
+            Outcome::Undoable { run, .. } => run.execute()?,
+        };
+        result.duration = start.elapsed();
+        Ok(result)
+    }
+
+    pub fn execute_on(&self, ql: &mut QueryList<'a>, vars: &mut Vars) -> Result<OutcomeResult> {
+        let start = Instant::now();
+        let mut result = match self {
+            Outcome::Modify(f) => {
+                f(ql)?;
+                OutcomeResult::default()
+            },
+            Outcome::Closure(f) => OutcomeResult::new(f(vars)?),
+            Outcome::SetVar { name, value } => {
+                vars.set(name, value.clone());
+                OutcomeResult::default()
+            },
+            Outcome::Compute { name, expr } => {
+                let value = compute(expr, vars)?;
+                vars.set(name, value);
+                OutcomeResult::default()
+            },
+            Outcome::Undoable { run, .. } => run.execute_on(ql, vars)?,
+            _ => return self.execute(),
+        };
+        result.duration = start.elapsed();
+        Ok(result)
+    }
+}
+
+// Evaluates the expression of a Compute outcome. Expressions that have no
+// value, like arithmetic on a variable that was never set, are an error
+// here rather than quietly unsetting the variable.
+pub fn compute(expr: &Expr, env: &dyn Env) -> Result<Value> {
+    match expr.eval(env) {
+        Some(value) => Ok(value),
+        None => bail!("`{}` has no value", expr.source()),
     }
 }
 
-pub fn run_external_cmd(cmd: String) -> Result<String> {
-    let args = cmd.split(" ").map(|s| s.to_string()).collect::<Vec<String>>();
-    let mut builder = Command::new(&args[0]);
-    let _ = &builder.args(&args[1..]);
-    
-    let out = builder.output()?;
-    
-    Ok(String::from_utf8(out.stdout)?)
+// Runs a command line without a shell, see `shell::run`
+pub fn run_external_cmd(cmd: String) -> Result<OutcomeResult> {
+    Ok(OutcomeResult::from_output(shell::run(&cmd)?))
 }
 
@@ -0,0 +1,187 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, stderr, stdin, stdout, BufRead, BufReader, IsTerminal, Write};
+
+use anyhow::Result;
+use termimad::MadSkin;
+
+use crate::querylist::{AnswerId, QueryId};
+use crate::report::Answered;
+
+// The key that goes back to the previous query, shown alongside the
+// numbered answers whenever there is somewhere to go back to
+pub const BACK_KEY: &str = "b";
+
+// What the user is shown when a query is asked. The text and answers have
+// already had their placeholders expanded, and answers whose guards fail
+// have been left out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Menu {
+    pub qid: QueryId,
+    pub label: Option<String>,
+    pub text: String,
+    pub answers: Vec<String>,
+    // the ids of `answers`, in the same order
+    pub answer_ids: Vec<AnswerId>,
+    // what each of `answers` stands for, where that isn't its text (see
+    // `Answer::value`)
+    pub values: Vec<Option<String>>,
+    pub can_go_back: bool,
+}
+
+// What the user did with a menu. `Answer` is an index into `Menu::answers`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pick {
+    Answer(usize),
+    Back,
+    // the user gave up on the session (for instance, closed the input)
+    Cancel,
+}
+
+// Puts a menu in front of the user and waits for them to pick something
+pub trait Prompter {
+    fn ask(&mut self, menu: &Menu) -> Result<Pick>;
+
+    // Shows what an outcome printed. By default it goes to stdout.
+    fn output(&mut self, text: &str) -> Result<()> {
+        stdout().write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    // Told about each answer once its outcomes have run, or one of them
+    // has failed, and about each answer that is taken back. By default
+    // nothing is done with them.
+    fn answered(&mut self, _answered: &Answered) -> Result<()> {
+        Ok(())
+    }
+
+    fn took_back(&mut self, _aid: &AnswerId) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub fn open_tty() -> io::Result<BufReader<File>> {
+    Ok(BufReader::new(File::open("/dev/tty")?))
+}
+
+// Where to read the user's keys from. When stdin is a pipe it carries
+// data rather than keys, so the controlling terminal is read instead (if
+// there is one).
+pub fn terminal_input() -> Box<dyn BufRead> {
+    if !stdin().is_terminal() {
+        if let Ok(tty) = open_tty() {
+            return Box::new(tty);
+        };
+    };
+    Box::new(stdin().lock())
+}
+
+// Where to draw menus. stdout is kept for results whenever it is
+// redirected, so `ls | weid --dmenu | xargs rm` only passes on the choice;
+// the menu goes to the terminal, or stderr if there isn't one.
+pub fn terminal_output() -> Box<dyn Write> {
+    if stdout().is_terminal() {
+        return Box::new(stdout());
+    };
+    if !stderr().is_terminal() {
+        if let Ok(tty) = OpenOptions::new().write(true).open("/dev/tty") {
+            return Box::new(tty);
+        };
+    };
+    Box::new(stderr())
+}
+
+// A markdown question on the terminal: numbered answers, plus `b` for back.
+// End of input cancels.
+pub struct TermPrompter<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    skin: MadSkin,
+}
+
+impl<R: BufRead, W: Write> TermPrompter<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        TermPrompter {
+            input,
+            output,
+            skin: MadSkin::default(),
+        }
+    }
+}
+
+impl TermPrompter<Box<dyn BufRead>, Box<dyn Write>> {
+    // Reads from and draws on the terminal, see `terminal_input` and
+    // `terminal_output`
+    pub fn terminal() -> Self {
+        TermPrompter::new(terminal_input(), terminal_output())
+    }
+}
+
+impl<R: BufRead, W: Write> Prompter for TermPrompter<R, W> {
+    fn ask(&mut self, menu: &Menu) -> Result<Pick> {
+        self.skin.write_text_on(&mut self.output, &menu.text)?;
+        for (i, a) in menu.answers.iter().enumerate() {
+            write!(self.output, "[{}] ", i + 1)?;
+            self.skin.write_text_on(&mut self.output, a)?;
+        };
+        if menu.can_go_back {
+            write!(self.output, "[{}] ", BACK_KEY)?;
+            self.skin.write_text_on(&mut self.output, "back")?;
+        };
+        self.output.flush()?;
+
+        loop {
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(Pick::Cancel);
+            };
+            let key = line.trim();
+
+            if key == BACK_KEY && menu.can_go_back {
+                return Ok(Pick::Back);
+            };
+            if let Ok(n) = key.parse::<usize>() {
+                if (1..=menu.answers.len()).contains(&n) {
+                    return Ok(Pick::Answer(n - 1));
+                };
+            };
+            writeln!(self.output, "answer {:?} not understood", key)?;
+            self.output.flush()?;
+        };
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ask(typed: &str, can_go_back: bool) -> (Pick, String) {
+        let menu = Menu {
+            qid: 0,
+            label: None,
+            text: "How are you feeling?".to_string(),
+            answers: vec!["good".to_string(), "bad".to_string()],
+            answer_ids: vec![AnswerId::new(0, 0), AnswerId::new(0, 1)],
+            values: vec![None, None],
+            can_go_back,
+        };
+        let mut out = Vec::new();
+        let pick = TermPrompter::new(typed.as_bytes(), &mut out).ask(&menu).unwrap();
+        (pick, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn term_prompter() {
+        let (pick, out) = ask("2\n", false);
+        assert_eq!(pick, Pick::Answer(1));
+        assert!(out.contains("How are you feeling?") && out.contains("[2] bad"), "{}", out);
+        assert!(!out.contains("back"));
+
+        let (pick, out) = ask("3\nb\n", false);
+        assert_eq!(pick, Pick::Cancel);
+        assert!(out.contains("answer \"3\" not understood\nanswer \"b\" not understood"), "{}", out);
+
+        assert_eq!(ask(" b \n", true).0, Pick::Back);
+        assert_eq!(ask("", true).0, Pick::Cancel);
+    }
+}
@@ -9,21 +9,38 @@ use std::marker::PhantomData;
 use std::iter::IntoIterator;
 
 use nanoid::nanoid;
-use anyhow::Result;
+use anyhow::{Result, bail};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::Error;
 
 use super::outcome::*;
+use super::expr::Expr;
+use super::records::Fill;
+use super::querier::RESERVED_EXIT_CODES;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Answer<'a> {
+    #[serde(rename = "text")]
     display: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    #[serde(default, rename = "if", skip_serializing_if = "Option::is_none")]
+    guard: Option<Expr>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     outcomes: Vec<Outcome<'a>>,
+    #[serde(default, rename = "exit", skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_exit_code")]
+    exit_code: Option<i32>,
 }
 
 impl<'a> Answer<'a> {
     pub fn from_text(display: String) -> Answer<'a> {
         Answer {
             display,
+            value: None,
+            guard: None,
             outcomes: Vec::new(),
+            exit_code: None,
         }
     }
 
@@ -31,25 +48,124 @@ impl<'a> Answer<'a> {
         self.display.clone()
     }
 
+    // What the answer stands for, as opposed to how it is shown: what
+    // reports, templates and expressions see. The text, unless one is set.
+    pub fn value(&self) -> String {
+        self.value.clone().unwrap_or_else(|| self.display.clone())
+    }
+
+    pub fn set_value(&mut self, value: String) {
+        self.value = Some(value);
+    }
+
+    // The answer is only offered while this holds
+    pub fn guard(&self) -> Option<&Expr> {
+        self.guard.as_ref()
+    }
+
+    pub fn set_guard(&mut self, guard: Expr) {
+        self.guard = Some(guard);
+    }
+
+    // What weid exits with if the session ends on this answer, see
+    // `Querier::exit_code`
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    pub fn set_exit_code(&mut self, code: i32) -> Result<()> {
+        self.exit_code = Some(check_exit_code(code)?);
+        Ok(())
+    }
+
     pub fn add_outcome(&mut self, outcome: Outcome<'a>) {
         self.outcomes.push(outcome);
     }
 
+    pub fn pop_outcome(&mut self) -> Option<Outcome<'a>> {
+        self.outcomes.pop()
+    }
+
     pub fn outcomes(&self) -> Vec<Outcome<'a>> {
         self.outcomes.clone()
     }
+
+    // A copy with `fill` applied to the text and outcomes, see `Outcome::fill`
+    pub fn fill(&self, fill: &dyn Fill) -> Answer<'a> {
+        Answer {
+            display: fill.text(&self.display),
+            value: self.value.as_deref().map(|v| fill.text(v)),
+            guard: self.guard.clone(),
+            outcomes: self.outcomes.iter().map(|o| o.fill(fill)).collect(),
+            exit_code: self.exit_code,
+        }
+    }
 }
 
+// Answers are the same if they stand for the same thing, however they
+// are shown
 impl<'a> PartialEq for Answer<'a> {
     fn eq(&self, other: &Self) -> bool {
-        self.display == other.display
+        self.value() == other.value()
     }
 }
 
 
-#[derive(Clone, Debug)]
+// Exit codes have to fit in a byte, and leave weid's own alone
+fn check_exit_code(code: i32) -> Result<i32> {
+    if !(0..=255).contains(&code) {
+        bail!("exit code {} is not between 0 and 255", code);
+    };
+    if RESERVED_EXIT_CODES.contains(&code) {
+        bail!("exit code {} is kept for weid's own use", code);
+    };
+    Ok(code)
+}
+
+fn deserialize_exit_code<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i32>, D::Error> {
+    check_exit_code(i32::deserialize(deserializer)?).map(Some).map_err(D::Error::custom)
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn is_default_weight(w: &f64) -> bool {
+    *w == default_weight()
+}
+
+// Weights are relative chances, so they have to be finite and not negative
+fn check_weight(weight: f64) -> Result<f64> {
+    if !weight.is_finite() || weight < 0.0 {
+        bail!("weight {} should be a number no less than 0", weight);
+    };
+    Ok(weight)
+}
+
+fn deserialize_weight<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    check_weight(f64::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+fn is_zero(n: &i64) -> bool {
+    *n == 0
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Query<'a> { 
+    #[serde(rename = "text")]
     display: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(default = "default_weight", skip_serializing_if = "is_default_weight", deserialize_with = "deserialize_weight")]
+    weight: f64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    priority: i64,
+    #[serde(default, rename = "if", skip_serializing_if = "Option::is_none")]
+    guard: Option<Expr>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     answers: Vec<Answer<'a>>,
 }
 
@@ -57,6 +173,11 @@ impl<'a> Query<'a> {
     pub fn from_text(display: String) -> Query<'a> {
         Query {
             display,
+            label: None,
+            key: None,
+            weight: default_weight(),
+            priority: 0,
+            guard: None,
             answers: Vec::new(),
         }
     }
@@ -65,6 +186,53 @@ impl<'a> Query<'a> {
         &self.display
     }
 
+    // A name other queries can refer to this one by, e.g. with `--goto`
+    pub fn label(&self) -> Option<&String> {
+        self.label.as_ref()
+    }
+
+    pub fn set_label(&mut self, label: String) {
+        self.label = Some(label);
+    }
+
+    // A name for what the query asks, for reports and expressions, as
+    // `Answer::value` is for answers
+    pub fn key(&self) -> Option<&String> {
+        self.key.as_ref()
+    }
+
+    pub fn set_key(&mut self, key: String) {
+        self.key = Some(key);
+    }
+
+    // Relative chance of being picked by the `Weighted` ordering strategy
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    pub fn set_weight(&mut self, weight: f64) -> Result<()> {
+        self.weight = check_weight(weight)?;
+        Ok(())
+    }
+
+    // Higher priorities are asked first by the `Priority` ordering strategy
+    pub fn priority(&self) -> i64 {
+        self.priority
+    }
+
+    pub fn set_priority(&mut self, priority: i64) {
+        self.priority = priority;
+    }
+
+    // The query is only asked while this holds
+    pub fn guard(&self) -> Option<&Expr> {
+        self.guard.as_ref()
+    }
+
+    pub fn set_guard(&mut self, guard: Expr) {
+        self.guard = Some(guard);
+    }
+
     pub fn add_answer(&mut self, answer: Answer<'a>) {
         self.answers.push(answer);
     }
@@ -78,14 +246,33 @@ impl<'a> Query<'a> {
         };
     }
 
+    // Puts answers ahead of the ones already defined, as default answers are
+    pub fn prepend_answers<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = Answer<'a>>,
+    {
+        let rest = std::mem::take(&mut self.answers);
+        self.add_answers(iter);
+        self.add_answers(rest);
+    }
+
     pub fn answers(&self) -> Vec<Answer<'a>> {
         self.answers.clone()
     }
+
+    // A copy with `fill` applied to the text and answers, see `Outcome::fill`
+    pub fn fill(&self, fill: &dyn Fill) -> Query<'a> {
+        Query {
+            display: fill.text(&self.display),
+            answers: self.answers.iter().map(|a| a.fill(fill)).collect(),
+            ..self.clone()
+        }
+    }
 }
 
 impl<'a> PartialEq for Query<'a> {
     fn eq(&self, other: &Self) -> bool {
-        self.display == other.display
+        self.key.as_ref().unwrap_or(&self.display) == other.key.as_ref().unwrap_or(&other.display)
     }
 }
 
@@ -1,57 +1,188 @@
 #![allow(dead_code, unused_variables)]
 
-use std::collections::hash_map::HashMap;
-use std::fmt::Debug;
-use std::io::{stdout, Write};
+use std::io::{stderr, stdout, Write};
+use std::str::FromStr;
+use std::time::Instant;
 
-use termimad::MadSkin;
-use termimad as t;
-use anyhow::{Context, Result};
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
 
 use super::qa::*;
 use super::querylist::*;
 use super::outcome::*;
+use super::order::*;
+use super::vars::*;
+use super::expr::*;
+use super::template;
+use super::prompt::*;
+use super::report::Answered;
 
-type OutcomeResult = String;
+pub use super::prompt::BACK_KEY;
+
+// Exit codes weid keeps for itself, so they can't be given to answers. The
+// cancelled code is what shells report for Ctrl-C.
+pub const EXIT_ERROR: i32 = 125;
+pub const EXIT_CANCELLED: i32 = 130;
+pub const RESERVED_EXIT_CODES: [i32; 2] = [EXIT_ERROR, EXIT_CANCELLED];
+
+// What to do after an answer that has no path attached to it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fallback {
+    // ask whichever query the ordering strategy picks. With the default
+    // sequential order, that is the one after the query just answered, so
+    // queries a path jumped over stay skipped.
+    #[default]
+    Next,
+    // end the session
+    Stop,
+}
+
+impl FromStr for Fallback {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "next" => Ok(Fallback::Next),
+            "stop" => Ok(Fallback::Stop),
+            _ => bail!("Unknown fallback {:?} (expected next or stop)", s),
+        }
+    }
+}
+
+// What the user picked when asked a query
+#[derive(Clone, Debug)]
+pub enum Choice<'a> {
+    Answer(AnswerId, Answer<'a>),
+    Back,
+    Cancel,
+}
+
+// One answered query, with whatever is needed to take it back
+struct Step<'a> {
+    qid: QueryId,
+    aid: AnswerId,
+    undo: Vec<Outcome<'a>>,
+    // the variables as they were before the answer's outcomes ran
+    vars: Vars,
+    answered: Answered,
+}
 
 pub struct Querier<'a> {
     ql: QueryList<'a>,
-    next: Option<QueryId>,
+    last: Option<AnswerId>,
     visited: Vec<QueryId>,
-
+    history: Vec<Step<'a>>,
+    redo: Option<QueryId>,
+    vars: Vars,
+    fallback: Fallback,
+    strategy: Box<dyn OrderingStrategy + 'a>,
+    // the terminal, unless set otherwise; opened when first needed
+    prompter: Option<Box<dyn Prompter + 'a>>,
+    cancelled: bool,
+    // the answer whose outcome failed, if one did
+    failed: Option<Answered>,
 }
 
 impl<'a> Querier<'a> {
-    pub fn new(qlist: QueryList) -> Querier {
+    pub fn new(qlist: QueryList<'a>) -> Querier<'a> {
+        Querier::with_strategy(qlist, Box::new(Sequential::default()))
+    }
+
+    pub fn with_strategy(qlist: QueryList<'a>, strategy: Box<dyn OrderingStrategy + 'a>) -> Querier<'a> {
         Querier {
             ql: qlist,
-            next: None,
+            last: None,
             visited: Vec::new(),
+            history: Vec::new(),
+            redo: None,
+            vars: Vars::new(),
+            fallback: Fallback::default(),
+            strategy,
+            prompter: None,
+            cancelled: false,
+            failed: None,
         }
     }
 
-    pub fn pick_next_query(&self) -> Option<QueryId> {
-        for key in self.ql.peek_queries().keys() {
-            if !self.visited.contains(key) {
-                return Some(key.clone());
+    pub fn set_fallback(&mut self, fallback: Fallback) {
+        self.fallback = fallback;
+    }
+
+    // Changes how queries are put to the user, see `weid::prompt`
+    pub fn set_prompter(&mut self, prompter: Box<dyn Prompter + 'a>) {
+        self.prompter = Some(prompter);
+    }
+
+    // Paths are looked up when the next query is picked rather than when
+    // the answer is recorded, so Modify outcomes can still add one.
+    pub fn pick_next_query(&mut self) -> Option<QueryId> {
+        if let Some(qid) = self.redo.take() {
+            return Some(qid);
+        };
+
+        if let Some(aid) = &self.last {
+            if let Some(qid) = self.ql.get_path(aid.clone()) {
+                if self.askable(qid) {
+                    return Some(qid);
+                };
+            };
+            if self.fallback == Fallback::Stop {
+                return None;
             };
         };
-        None
+
+        let candidates: Vec<QueryId> = self.ql.order().iter().copied()
+            .filter(|qid| self.askable(*qid))
+            .collect();
+        let last = self.last.as_ref().map(|aid| aid.qid());
+        self.strategy.pick(&self.ql, &candidates, &self.visited, last)
     }
 
-    pub fn get_next_query(&self) -> Option<Query> {
-        match self.next {
-            None => {
-                let qid = self.pick_next_query()?;
-                self.get_query(qid)
-            },
-            Some(qid) => {
-                self.get_query(qid)
-            },
-        }
+    // Expands the placeholders in query or answer text against the session
+    // as it stands, see `template::render`
+    pub fn render(&self, text: &str) -> String {
+        template::render(text, self)
+    }
+
+    // Whether a query or answer with this guard should be offered right now
+    pub fn holds(&self, guard: Option<&Expr>) -> bool {
+        guard.is_none_or(|g| g.is_true(self))
+    }
+
+    // Whether a query can be asked right now: its guard holds, and unless
+    // it has no answers at all, at least one of them passes its guard.
+    // Otherwise it would be a menu with nothing to pick.
+    pub fn askable(&self, qid: QueryId) -> bool {
+        let Some(query) = self.get_query(qid) else {
+            return false;
+        };
+        self.holds(query.guard()) && (query.answers().is_empty() || !self.available_answers(qid).is_empty())
+    }
+
+    // The answers to a query that pass their guards, with their ids
+    pub fn available_answers(&self, qid: QueryId) -> Vec<(AnswerId, Answer<'a>)> {
+        let Some(query) = self.get_query(qid) else {
+            return Vec::new();
+        };
+        query.answers().into_iter().enumerate()
+            .filter(|(_, a)| self.holds(a.guard()))
+            .map(|(sub, a)| (AnswerId::new(qid, sub), a))
+            .collect()
+    }
+
+    // Adds queries to the end of the session's list, for instance ones that
+    // arrived on a control channel while it was running
+    pub fn append_queries(&mut self, ql: QueryList<'a>) {
+        self.ql.append(ql);
     }
 
-    pub fn get_query(&self, qid: QueryId) -> Option<Query> {
+    pub fn get_next_query(&mut self) -> Option<Query<'a>> {
+        let qid = self.pick_next_query()?;
+        self.get_query(qid)
+    }
+
+    pub fn get_query(&self, qid: QueryId) -> Option<Query<'a>> {
         self.ql.get_query(qid)
     }
 
@@ -59,31 +190,635 @@ impl<'a> Querier<'a> {
         self.visited.push(qid)
     }
 
-    pub fn execute_query(&self, query: &Query<'a>) -> Result<Answer<'a>> {
+    // Remembers the chosen answer so the next pick can follow its path.
+    pub fn record_answer(&mut self, aid: AnswerId) {
+        self.last = Some(aid);
+    }
+
+    pub fn vars(&self) -> &Vars {
+        &self.vars
+    }
+
+    pub fn vars_mut(&mut self) -> &mut Vars {
+        &mut self.vars
+    }
+
+    pub fn can_go_back(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    // The answers chosen so far, oldest first, not counting any that were
+    // taken back
+    pub fn answered(&self) -> Vec<AnswerId> {
+        self.history.iter().map(|s| s.aid.clone()).collect()
+    }
+
+    // What each answer chosen so far did, oldest first, ending with the
+    // one whose outcome failed if there is one
+    pub fn report(&self) -> Vec<Answered> {
+        self.history.iter().map(|s| s.answered.clone()).chain(self.failed.clone()).collect()
+    }
+
+    // What weid should exit with: EXIT_CANCELLED if the session was
+    // cancelled, or else the exit code of the answer given to query `from`,
+    // or without one, of the last answer that has a code. 0 if there is
+    // no such code.
+    pub fn exit_code(&self, from: Option<&str>) -> Result<i32> {
+        if self.cancelled {
+            return Ok(EXIT_CANCELLED);
+        };
+        let code = |s: &Step| self.ql.get_answer(s.aid.clone()).and_then(|a| a.exit_code());
+
+        let code = match from {
+            Some(query) => {
+                let Some(qid) = self.ql.find_query(query) else {
+                    bail!("No query {:?} to take the exit code from", query);
+                };
+                self.history.iter().rev().find(|s| s.qid == qid).and_then(code)
+            },
+            None => self.history.iter().rev().find_map(code),
+        };
+        Ok(code.unwrap_or(0))
+    }
+
+    // Whether the session ended because the user cancelled it
+    pub fn cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    // Asks the next query and runs the outcomes of the chosen answer.
+    // Returns false once there is nothing left to ask, or the user cancels.
+    pub fn step(&mut self) -> Result<bool> {
+        let Some(qid) = self.pick_next_query() else {
+            return Ok(false);
+        };
+        self.mark_visited(qid);
+
+        match self.execute_query(qid)? {
+            Choice::Back => {
+                self.unvisit(qid);
+                self.back()?;
+            },
+            Choice::Answer(aid, _) => {
+                self.choose_answer(aid)?;
+            },
+            Choice::Cancel => {
+                self.unvisit(qid);
+                self.cancelled = true;
+                return Ok(false);
+            },
+        };
+
+        Ok(true)
+    }
 
-        let text = query.display();
+    // Runs the outcomes of an answer to a query that has already been
+    // marked visited, and records it so it can be followed or backed out of.
+    pub fn choose_answer(&mut self, aid: AnswerId) -> Result<()> {
+        let Some(answer) = self.ql.get_answer(aid.clone()) else {
+            bail!("No answer with id {:?}", aid);
+        };
+
+        let query = self.get_query(aid.qid());
+        let mut answered = Answered {
+            query: aid.qid(),
+            label: query.as_ref().and_then(|q| q.label().cloned()),
+            key: query.as_ref().and_then(|q| q.key().cloned()),
+            answer: aid.clone(),
+            text: self.render(&answer.display()),
+            value: answer.value(),
+            outcomes: Vec::new(),
+        };
+        self.failed = None;
+
+        let vars = self.vars.clone();
+        let mut undo = Vec::new();
+        for o in answer.outcomes() {
+            undo.extend(o.undo());
+            let start = Instant::now();
+            match self.execute_outcome(o) {
+                Ok(result) => answered.outcomes.push(result),
+                Err(e) => {
+                    let mut result = OutcomeResult::failed(&e);
+                    result.duration = start.elapsed();
+                    answered.outcomes.push(result);
+                    if let Some(prompter) = &mut self.prompter {
+                        prompter.answered(&answered)?;
+                    };
+                    self.failed = Some(answered);
+                    return Err(e);
+                },
+            };
+        };
+        if let Some(prompter) = &mut self.prompter {
+            prompter.answered(&answered)?;
+        };
+        self.history.push(Step { qid: aid.qid(), aid: aid.clone(), undo, vars, answered });
+        self.record_answer(aid);
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        while self.step()? {};
+        Ok(())
+    }
+
+    // Takes back the most recent answer: its undo outcomes are run in
+    // reverse order, the variables go back to what they were before it,
+    // and its query will be asked again next. Returns false if nothing has
+    // been answered yet.
+    pub fn back(&mut self) -> Result<bool> {
+        let Some(step) = self.history.pop() else {
+            return Ok(false);
+        };
+
+        for o in step.undo.into_iter().rev() {
+            self.execute_outcome(o)?;
+        };
+        self.vars = step.vars;
+        if let Some(prompter) = &mut self.prompter {
+            prompter.took_back(&step.aid)?;
+        };
 
-        // set up Termimad question engine
-        let mut q = t::Question::new(text);
-        let skin = MadSkin::default();
+        self.unvisit(step.qid);
+        self.last = self.history.last().map(|s| s.aid.clone());
+        self.redo = Some(step.qid);
+        Ok(true)
+    }
 
-        // add answers to engine, making a new map to keep track of ids
-        let answers = query.answers();
-        for (i,a) in answers.iter().enumerate() {
-            q.add_answer(i+1, a.display());
+    pub fn unvisit(&mut self, qid: QueryId) {
+        if let Some(pos) = self.visited.iter().rposition(|v| *v == qid) {
+            self.visited.remove(pos);
         };
-       
-        //actually prompt the user with the question, get resulting "key"
-        let ans = q.ask(&skin)?.parse::<usize>()?;
-        
-        //return Answer
-        Ok(answers.get(ans-1).unwrap().to_owned())
+    }
+
+    // A query as it should be shown right now: placeholders expanded, and
+    // only the answers whose guards hold
+    pub fn menu(&self, qid: QueryId) -> Result<Menu> {
+        let Some(query) = self.get_query(qid) else {
+            bail!("No query with id {}", qid);
+        };
+
+        let answers = self.available_answers(qid);
+        Ok(Menu {
+            qid,
+            label: query.label().cloned(),
+            text: self.render(query.display()),
+            answers: answers.iter().map(|(_, a)| self.render(&a.display())).collect(),
+            answer_ids: answers.iter().map(|(aid, _)| aid.clone()).collect(),
+            values: answers.iter().map(|(_, a)| Some(a.value()).filter(|v| *v != a.display())).collect(),
+            can_go_back: self.can_go_back(),
+        })
+    }
+
+    pub fn execute_query(&mut self, qid: QueryId) -> Result<Choice<'a>> {
+        let menu = self.menu(qid)?;
+
+        let prompter = self.prompter.get_or_insert_with(|| Box::new(TermPrompter::terminal()));
+        match prompter.ask(&menu)? {
+            Pick::Back => Ok(Choice::Back),
+            Pick::Cancel => Ok(Choice::Cancel),
+            Pick::Answer(i) => {
+                let answer = menu.answer_ids.get(i).and_then(|aid| Some((aid.clone(), self.ql.get_answer(aid.clone())?)));
+                let Some((aid, answer)) = answer else {
+                    bail!("No answer {} to query {}", i + 1, qid);
+                };
+                Ok(Choice::Answer(aid, answer))
+            },
+        }
     }
     
-    pub fn execute_outcome(&mut self, outcome: Outcome<'a>) -> Result<String> {
-        let out = outcome.execute()?;
-        stdout().write_all(out.as_bytes())?;
-        Ok(out)
+    // Runs an outcome between queries. Modify outcomes get the live
+    // QueryList, so their changes apply to the rest of the session, and
+    // closures get the session variables.
+    pub fn execute_outcome(&mut self, outcome: Outcome<'a>) -> Result<OutcomeResult> {
+        // expressions can see the whole session, not just the variables
+        match outcome {
+            Outcome::Compute { name, expr } => {
+                let start = Instant::now();
+                let value = compute(&expr, self)?;
+                self.vars.set(&name, value);
+                return Ok(OutcomeResult { duration: start.elapsed(), ..Default::default() });
+            },
+            Outcome::Undoable { run, .. } => return self.execute_outcome(*run),
+            _ => {},
+        };
+
+        let result = outcome.execute_on(&mut self.ql, &mut self.vars)?;
+        match &mut self.prompter {
+            Some(prompter) => prompter.output(&result.stdout)?,
+            None => stdout().write_all(result.stdout.as_bytes())?,
+        };
+        stderr().write_all(result.stderr.as_bytes())?;
+        Ok(result)
+    }
+
+}
+
+impl<'a> Env for Querier<'a> {
+    fn var(&self, name: &str) -> Option<Value> {
+        self.vars.get(name).cloned()
+    }
+
+    // The value of the answer most recently given to the query
+    fn answer(&self, query: &str) -> Option<Value> {
+        let qid = self.ql.find_query(query)?;
+        let step = self.history.iter().rev().find(|s| s.qid == qid)?;
+        self.ql.get_answer(step.aid.clone()).map(|a| Value::Str(a.value()))
+    }
+
+    fn visited(&self, query: &str) -> bool {
+        self.ql.find_query(query).is_some_and(|qid| self.visited.contains(&qid))
+    }
+
+    // The exit status of the last command run by that answer
+    fn status(&self, query: &str) -> Option<Value> {
+        let qid = self.ql.find_query(query)?;
+        let step = self.history.iter().rev().find(|s| s.qid == qid)?;
+        step.answered.outcomes.iter().rev().find_map(|r| r.status).map(|s| Value::Int(s.into()))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn modify_outcome() {
+        let mut ql = QueryList::new();
+        let q0 = ql.insert_query(Query::from_text("q0".to_string()));
+        let q1 = ql.insert_query(Query::from_text("q1".to_string()));
+
+        let mut querier = Querier::new(ql);
+        assert_eq!(querier.pick_next_query(), Some(q0));
+        querier.mark_visited(q0);
+
+        let follow_up = Outcome::new_modify(|ql| {
+            ql.insert_query_at(0, Query::from_text("follow-up".to_string()));
+            Ok(())
+        });
+        querier.execute_outcome(follow_up).unwrap();
+
+        let next = querier.pick_next_query().unwrap();
+        assert_ne!(next, q1);
+        assert_eq!(querier.get_query(next).unwrap().display(), "follow-up");
+        assert!(Outcome::new_modify(|_| Ok(())).execute().is_err());
+    }
+
+    #[test]
+    fn modify_after_answer() {
+        let mut ql = QueryList::new();
+        let q0 = ql.insert_query(Query::from_text("q0".to_string()));
+        let q1 = ql.insert_query(Query::from_text("q1".to_string()));
+        let q2 = ql.insert_query(Query::from_text("q2".to_string()));
+        ql.add_path(AnswerId::new(q0, 0), q2);
+
+        let mut querier = Querier::new(ql);
+        assert_eq!(querier.pick_next_query(), Some(q0));
+        querier.mark_visited(q0);
+        querier.record_answer(AnswerId::new(q0, 0));
+        assert_eq!(querier.pick_next_query(), Some(q2));
+        querier.mark_visited(q2);
+        querier.record_answer(AnswerId::new(q2, 0));
+
+        let follow_up = Outcome::new_modify(|ql| {
+            ql.insert_query_at(0, Query::from_text("follow-up".to_string()));
+            Ok(())
+        });
+        querier.execute_outcome(follow_up).unwrap();
+
+        // the inserted query is asked, but q1 was jumped over and stays skipped
+        let next = querier.pick_next_query().unwrap();
+        assert_eq!(querier.get_query(next).unwrap().display(), "follow-up");
+        querier.mark_visited(next);
+        querier.record_answer(AnswerId::new(next, 0));
+        assert_ne!(querier.pick_next_query(), Some(q1));
+        assert_eq!(querier.pick_next_query(), None);
+    }
+
+    #[test]
+    fn follow_paths() {
+        let mut ql = QueryList::new();
+        let q0 = ql.insert_query(Query::from_text("q0".to_string()));
+        let q1 = ql.insert_query(Query::from_text("q1".to_string()));
+        let q2 = ql.insert_query(Query::from_text("q2".to_string()));
+        ql.add_path(AnswerId::new(q0, 1), q2);
+
+        let mut querier = Querier::new(ql.clone());
+        querier.mark_visited(q0);
+        querier.record_answer(AnswerId::new(q0, 1));
+        assert_eq!(querier.pick_next_query(), Some(q2));
+
+        // no path on this answer, so fall back to the list order
+        querier.record_answer(AnswerId::new(q0, 0));
+        assert_eq!(querier.pick_next_query(), Some(q1));
+
+        querier.set_fallback(Fallback::Stop);
+        assert_eq!(querier.pick_next_query(), None);
+        querier.record_answer(AnswerId::new(q0, 1));
+        assert_eq!(querier.pick_next_query(), Some(q2));
+
+        // q1 was jumped over, so sequential order doesn't pick it up after q2
+        querier.set_fallback(Fallback::Next);
+        querier.mark_visited(q2);
+        querier.record_answer(AnswerId::new(q2, 0));
+        assert_eq!(querier.pick_next_query(), None);
+
+        // nothing answered yet: the fallback doesn't apply
+        let mut querier = Querier::new(ql);
+        assert_eq!(querier.pick_next_query(), Some(q0));
     }
 
+    #[test]
+    fn jumped_over_queries() {
+        let mut ql = QueryList::new();
+        let mut q0 = Query::from_text("q0".to_string());
+        q0.add_answer(Answer::from_text("skip".to_string()));
+        let q0 = ql.insert_query(q0);
+        for text in ["q1", "q2", "q3"] {
+            let mut q = Query::from_text(text.to_string());
+            q.add_answer(Answer::from_text("ok".to_string()));
+            ql.insert_query(q);
+        };
+        ql.add_path(AnswerId::new(q0, 0), 2);
+
+        // Next carries on after the query jumped to, so q1 is never asked
+        let mut querier = Querier::new(ql.clone());
+        querier.set_prompter(Box::new(crate::batch::BatchPrompter::new(vec!["1".to_string(); 3])));
+        querier.run().unwrap();
+        assert_eq!(querier.answered(), [AnswerId::new(0, 0), AnswerId::new(2, 0), AnswerId::new(3, 0)]);
+
+        // Stop ends the session at the first answer without a path
+        let mut querier = Querier::new(ql);
+        querier.set_fallback("stop".parse().unwrap());
+        querier.set_prompter(Box::new(crate::batch::BatchPrompter::new(vec!["1".to_string(); 3])));
+        querier.run().unwrap();
+        assert_eq!(querier.answered(), [AnswerId::new(0, 0), AnswerId::new(2, 0)]);
+
+        assert!("sideways".parse::<Fallback>().is_err());
+    }
+
+    #[test]
+    fn paths_before_strategy() {
+        let mut ql = QueryList::new();
+        let mut q0 = Query::from_text("q0".to_string());
+        q0.set_priority(1);
+        let q0 = ql.insert_query(q0);
+        let q1 = ql.insert_query(Query::from_text("q1".to_string()));
+        let mut q2 = Query::from_text("q2".to_string());
+        q2.set_priority(2);
+        let q2 = ql.insert_query(q2);
+        ql.add_path(AnswerId::new(q2, 0), q1);
+
+        let mut querier = Querier::with_strategy(ql, Box::new(Priority));
+        assert_eq!(querier.pick_next_query(), Some(q2));
+        querier.mark_visited(q2);
+        querier.record_answer(AnswerId::new(q2, 1));
+        assert_eq!(querier.pick_next_query(), Some(q0));
+        querier.record_answer(AnswerId::new(q2, 0));
+        assert_eq!(querier.pick_next_query(), Some(q1));
+    }
+
+    #[test]
+    fn back_and_undo() {
+        use std::cell::RefCell;
+
+        let log = RefCell::new(Vec::new());
+
+        let mut ql = QueryList::new();
+        let mut a = Answer::from_text("a".to_string());
+        let run = Outcome::new_closure(|| { log.borrow_mut().push("run"); Ok("".to_string()) });
+        let undo = Outcome::new_closure(|| { log.borrow_mut().push("undo"); Ok("".to_string()) });
+        a.add_outcome(run.with_undo(undo));
+        let mut q0 = Query::from_text("q0".to_string());
+        q0.add_answer(a);
+        let q0 = ql.insert_query(q0);
+        let q1 = ql.insert_query(Query::from_text("q1".to_string()));
+        ql.add_path(AnswerId::new(q0, 0), q1);
+
+        let mut querier = Querier::new(ql);
+        assert!(!querier.back().unwrap());
+
+        let qid = querier.pick_next_query().unwrap();
+        querier.mark_visited(qid);
+        querier.choose_answer(AnswerId::new(q0, 0)).unwrap();
+        assert_eq!(querier.pick_next_query(), Some(q1));
+
+        assert!(querier.can_go_back());
+        assert!(querier.back().unwrap());
+        assert_eq!(*log.borrow(), ["run", "undo"]);
+        assert!(querier.visited.is_empty());
+        assert_eq!(querier.pick_next_query(), Some(q0));
+        assert!(!querier.can_go_back());
+    }
+
+    #[test]
+    fn answer_values() {
+        let mut ql = QueryList::new();
+        let mut q0 = Query::from_text("What now?".to_string());
+        q0.set_key("action".to_string());
+        let mut delete = Answer::from_text("Delete it (irreversible)".to_string());
+        delete.set_value("delete".to_string());
+        q0.add_answer(delete);
+        let q0 = ql.insert_query(q0);
+        let mut q1 = Query::from_text("Really {{answer.action}}?".to_string());
+        q1.set_guard(Expr::parse("answer(action) == 'delete'").unwrap());
+        let q1 = ql.insert_query(q1);
+
+        let mut querier = Querier::new(ql);
+        querier.mark_visited(q0);
+        querier.choose_answer(AnswerId::new(q0, 0)).unwrap();
+        assert_eq!(querier.pick_next_query(), Some(q1));
+        assert_eq!(querier.menu(q1).unwrap().text, "Really delete?");
+        // menus show the text, reports give both
+        assert_eq!(querier.menu(q0).unwrap().answers, ["Delete it (irreversible)"]);
+        assert_eq!(querier.menu(q0).unwrap().values, [Some("delete".to_string())]);
+        let report = &querier.report()[0];
+        assert_eq!((report.key.as_deref(), report.text.as_str(), report.value.as_str()),
+            (Some("action"), "Delete it (irreversible)", "delete"));
+    }
+
+    #[test]
+    fn outcome_status() {
+        let mut ql = QueryList::new();
+        let mut q0 = Query::from_text("Check?".to_string());
+        let mut check = Answer::from_text("yes".to_string());
+        check.add_outcome(Outcome::new_shell("exit 2".to_string()));
+        q0.add_answer(check);
+        let q0 = ql.insert_query(q0);
+        let mut passed = Query::from_text("Passed.".to_string());
+        passed.set_guard(Expr::parse("status(q0) == 0").unwrap());
+        ql.insert_query(passed);
+        let mut failed = Query::from_text("Failed, retry?".to_string());
+        failed.set_guard(Expr::parse("status(q0) != 0").unwrap());
+        let failed = ql.insert_query(failed);
+
+        let mut querier = Querier::new(ql);
+        querier.mark_visited(q0);
+        querier.choose_answer(AnswerId::new(q0, 0)).unwrap();
+        assert_eq!(querier.pick_next_query(), Some(failed));
+
+        let result = &querier.report()[0].outcomes[0];
+        assert_eq!(result.status, Some(2));
+        assert_eq!(result.error.as_ref().map(|e| e.kind), Some(ErrorKind::Status));
+        assert_eq!(querier.exit_code(None).unwrap(), 0);
+    }
+
+    #[test]
+    fn exit_codes() {
+        let mut ql = QueryList::new();
+        let mut q0 = Query::from_text("deploy?".to_string());
+        q0.set_label("deploy".to_string());
+        let mut yes = Answer::from_text("yes".to_string());
+        yes.set_exit_code(0).unwrap();
+        let mut no = Answer::from_text("no".to_string());
+        no.set_exit_code(3).unwrap();
+        q0.add_answers([yes, no]);
+        let q0 = ql.insert_query(q0);
+        let mut q1 = Query::from_text("done".to_string());
+        q1.add_answer(Answer::from_text("ok".to_string()));
+        let q1 = ql.insert_query(q1);
+
+        let mut querier = Querier::new(ql);
+        assert_eq!(querier.exit_code(None).unwrap(), 0);
+        querier.mark_visited(q0);
+        querier.choose_answer(AnswerId::new(q0, 1)).unwrap();
+        querier.mark_visited(q1);
+        querier.choose_answer(AnswerId::new(q1, 0)).unwrap();
+
+        // the last answer with a code, or the one given to a named query
+        assert_eq!(querier.exit_code(None).unwrap(), 3);
+        assert_eq!(querier.exit_code(Some("deploy")).unwrap(), 3);
+        assert_eq!(querier.exit_code(Some("q1")).unwrap(), 0);
+        assert!(querier.exit_code(Some("missing")).is_err());
+
+        querier.cancelled = true;
+        assert_eq!(querier.exit_code(None).unwrap(), EXIT_CANCELLED);
+    }
+
+    #[test]
+    fn session_vars() {
+        let mut querier = Querier::new(QueryList::new());
+
+        querier.execute_outcome(Outcome::new_set_var("count", 1)).unwrap();
+        let bump = Outcome::new_closure_with_vars(|vars| {
+            let Some(Value::Int(n)) = vars.get("count") else {
+                bail!("count isn't set");
+            };
+            vars.set("count", n + 1);
+            Ok("".to_string())
+        });
+        querier.execute_outcome(bump.clone()).unwrap();
+        querier.execute_outcome(bump).unwrap();
+
+        assert_eq!(querier.vars().get("count"), Some(&Value::Int(3)));
+        assert!(Outcome::new_set_var("x", true).execute().is_err());
+        assert!(Outcome::new_closure(|| Ok("".to_string())).execute().is_err());
+    }
+
+    #[test]
+    fn back_restores_vars() {
+        let mut ql = QueryList::new();
+        let mut q0 = Query::from_text("Where?".to_string());
+        let mut prod = Answer::from_text("production".to_string());
+        prod.add_outcome(Outcome::new_set_var("target", "production"));
+        prod.add_outcome(Outcome::new_compute("risk", Expr::parse("risk + 1").unwrap()));
+        q0.add_answer(prod);
+        let q0 = ql.insert_query(q0);
+
+        let mut querier = Querier::new(ql);
+        querier.vars_mut().set("risk", 1);
+        querier.mark_visited(q0);
+        querier.choose_answer(AnswerId::new(q0, 0)).unwrap();
+        assert_eq!(querier.vars().get("risk"), Some(&Value::Int(2)));
+
+        assert!(querier.back().unwrap());
+        assert_eq!(querier.vars().get("target"), None);
+        assert_eq!(querier.vars().get("risk"), Some(&Value::Int(1)));
+    }
+
+    #[test]
+    fn computed_vars() {
+        let expr = |src: &str| Expr::parse(src).unwrap();
+
+        let mut ql = QueryList::new();
+        let mut q0 = Query::from_text("how many?".to_string());
+        q0.add_answer(Answer::from_text("4".to_string()));
+        let q0 = ql.insert_query(q0);
+
+        let mut querier = Querier::new(ql);
+        querier.mark_visited(q0);
+        querier.choose_answer(AnswerId::new(q0, 0)).unwrap();
+        querier.execute_outcome(Outcome::new_set_var("score", 1)).unwrap();
+        querier.execute_outcome(Outcome::new_compute("score", expr("score + answer(q0) * 2"))).unwrap();
+        assert_eq!(querier.vars().get("score"), Some(&Value::Int(9)));
+
+        let err = querier.execute_outcome(Outcome::new_compute("x", expr("missing + 1"))).unwrap_err();
+        assert_eq!(err.to_string(), "`missing + 1` has no value");
+        assert_eq!(querier.vars().get("x"), None);
+
+        assert_eq!(querier.render("{{answer.q0}} makes {{var.score}}"), "4 makes 9");
+    }
+
+    #[test]
+    fn guards() {
+        let guard = |src: &str| Expr::parse(src).unwrap();
+
+        let mut ql = QueryList::new();
+        let mut action = Query::from_text("action?".to_string());
+        action.set_label("action".to_string());
+        action.add_answers(vec![
+            Answer::from_text("deploy".to_string()),
+            Answer::from_text("test".to_string()),
+        ]);
+        let action = ql.insert_query(action);
+        let mut target = Query::from_text("target?".to_string());
+        target.set_guard(guard("answer(action) == 'deploy'"));
+        let target = ql.insert_query(target);
+        let mut post = Query::from_text("post".to_string());
+        let mut unread = Answer::from_text("mark unread".to_string());
+        unread.set_guard(guard("!unread"));
+        post.add_answers(vec![Answer::from_text("open".to_string()), unread]);
+        let post = ql.insert_query(post);
+
+        let mut querier = Querier::new(ql.clone());
+        querier.mark_visited(action);
+        querier.choose_answer(AnswerId::new(action, 1)).unwrap();
+        assert_eq!(querier.pick_next_query(), Some(post));
+
+        let mut querier = Querier::new(ql.clone());
+        querier.mark_visited(action);
+        querier.choose_answer(AnswerId::new(action, 0)).unwrap();
+        assert!(querier.visited("action") && !querier.visited("q1"));
+        assert_eq!(querier.pick_next_query(), Some(target));
+
+        // guarded answers are left out, but keep their ids
+        assert_eq!(querier.available_answers(post).len(), 2);
+        querier.execute_outcome(Outcome::new_set_var("unread", true)).unwrap();
+        let answers = querier.available_answers(post);
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].0, AnswerId::new(post, 0));
+
+        // a path to a query whose guard fails falls back to the strategy
+        ql.add_path(AnswerId::new(action, 1), target);
+        let mut querier = Querier::new(ql);
+        querier.mark_visited(action);
+        querier.choose_answer(AnswerId::new(action, 1)).unwrap();
+        assert_eq!(querier.pick_next_query(), Some(post));
+
+        // a query whose answers are all guarded out is skipped
+        let mut ql = QueryList::new();
+        let mut first = Query::from_text("first".to_string());
+        let mut only = Answer::from_text("only if x".to_string());
+        only.set_guard(guard("x"));
+        first.add_answer(only);
+        ql.insert_query(first);
+        let second = ql.insert_query(Query::from_text("second".to_string()));
+        let mut querier = Querier::new(ql);
+        assert!(!querier.askable(0) && querier.askable(second));
+        assert_eq!(querier.pick_next_query(), Some(second));
+        querier.vars_mut().set("x", true);
+        assert_eq!(querier.pick_next_query(), Some(0));
+    }
 }
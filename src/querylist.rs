@@ -2,34 +2,81 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
 use std::fmt;
 use std::cmp::PartialEq;
+use std::str::FromStr;
 
-use anyhow::Result;
-use rand::seq::IteratorRandom;
+use anyhow::{Result, anyhow, bail};
+use rand::Rng;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use super::outcome::Outcome;
 use super::qa::*;
 
 pub type QueryId = usize;
 
-#[derive(PartialEq, Clone, Eq, Hash)]
+#[derive(PartialEq, Clone, Eq, Hash, PartialOrd, Ord)]
 pub struct AnswerId {
     qid: usize,
     sub: usize,
 }
 
+impl AnswerId {
+    // `sub` is the position of the answer within its query's answers
+    pub fn new(qid: QueryId, sub: usize) -> AnswerId {
+        AnswerId { qid, sub }
+    }
+
+    pub fn qid(&self) -> QueryId {
+        self.qid
+    }
+
+    pub fn sub(&self) -> usize {
+        self.sub
+    }
+}
+
 impl fmt::Debug for AnswerId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "q{}a{}", self.qid, self.sub)
     }
 }
 
+impl FromStr for AnswerId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let err = || anyhow!("Malformed AnswerId {:?}: expected something like \"q0a1\"", s);
+        let (qid, sub) = s.strip_prefix('q')
+            .and_then(|rest| rest.split_once('a'))
+            .ok_or_else(err)?;
+        Ok(AnswerId {
+            qid: qid.parse().map_err(|_| err())?,
+            sub: sub.parse().map_err(|_| err())?,
+        })
+    }
+}
+
+impl Serialize for AnswerId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{:?}", self))
+    }
+}
+
+impl<'de> Deserialize<'de> for AnswerId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct QueryList<'a> {
     queries: HashMap<QueryId, Query<'a>>,
+    order: Vec<QueryId>,
     paths: HashMap<AnswerId,QueryId>,
     next_id: usize,
 }
@@ -39,6 +86,7 @@ impl<'a> QueryList<'a> {
     pub fn new() -> Self {
         QueryList {
             queries: HashMap::new(),
+            order: Vec::new(),
             paths: HashMap::new(),
             next_id: 0,
         }
@@ -49,19 +97,49 @@ impl<'a> QueryList<'a> {
     }
 
     pub fn get_path(&self, aid: AnswerId) -> Option<QueryId> {
-        self.paths.get(&aid).map(|qid| qid.clone())
+        self.paths.get(&aid).copied()
     }
 
     pub fn get_random_query(&self) -> Option<Query<'a>> {
         let mut r = rand::thread_rng();
-        match self.queries.keys().choose(&mut r) {
+        match self.random_query_id(&mut r) {
             None => None,
-            Some(qid) => self.get_query(*qid),
+            Some(qid) => self.get_query(qid),
         }
     }
 
+    // Picks from the list order rather than the map, so a seeded rng
+    // gives the same picks every time
+    pub fn random_query_id<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<QueryId> {
+        self.order.choose(rng).copied()
+    }
+
     pub fn get_query(&self, qid: QueryId) -> Option<Query<'a>> {
-        self.queries.get(&qid).map(|q| q.clone())
+        self.queries.get(&qid).cloned()
+    }
+
+    pub fn find_label(&self, label: &str) -> Option<QueryId> {
+        self.order.iter()
+            .find(|qid| self.queries[qid].label().map(|l| l.as_str()) == Some(label))
+            .copied()
+    }
+
+    pub fn find_key(&self, key: &str) -> Option<QueryId> {
+        self.order.iter()
+            .find(|qid| self.queries[qid].key().map(|k| k.as_str()) == Some(key))
+            .copied()
+    }
+
+    // Looks a query up by label, then key, then `q<id>`
+    pub fn find_query(&self, name: &str) -> Option<QueryId> {
+        self.find_label(name).or_else(|| self.find_key(name)).or_else(|| {
+            let qid = name.strip_prefix('q')?.parse().ok()?;
+            self.queries.contains_key(&qid).then_some(qid)
+        })
+    }
+
+    pub fn get_answer(&self, aid: AnswerId) -> Option<Answer<'a>> {
+        self.queries.get(&aid.qid)?.answers().get(aid.sub).cloned()
     }
 
     pub fn get_next_query(&self, aid: AnswerId) -> Option<Query<'a>> {
@@ -74,15 +152,112 @@ impl<'a> QueryList<'a> {
     pub fn insert_query(&mut self, query: Query<'a>) -> QueryId {
         let out_qid = self.next_id;
         self.queries.insert(out_qid, query);
-        self.next_id = self.next_id + 1;
+        self.order.push(out_qid);
+        self.next_id += 1;
         out_qid
     }
 
+    pub fn insert_query_at(&mut self, index: usize, query: Query<'a>) -> QueryId {
+        let out_qid = self.insert_query(query);
+        self.move_query(out_qid, index).unwrap();
+        out_qid
+    }
+
+    // Removes a query along with any paths leading to or from it.
+    pub fn remove_query(&mut self, qid: QueryId) -> Option<Query<'a>> {
+        let query = self.queries.remove(&qid)?;
+        self.order.retain(|id| *id != qid);
+        self.paths.retain(|aid, target| aid.qid != qid && *target != qid);
+        Some(query)
+    }
+
+    // Moves a query to `index` in the list order. Indexes past the end
+    // move it to the end.
+    pub fn move_query(&mut self, qid: QueryId, index: usize) -> Result<()> {
+        let Some(pos) = self.order.iter().position(|id| *id == qid) else {
+            bail!("No query with id {}", qid);
+        };
+        self.order.remove(pos);
+        self.order.insert(index.min(self.order.len()), qid);
+        Ok(())
+    }
+
+    pub fn order(&self) -> &[QueryId] {
+        &self.order
+    }
+
+    // Moves every query of `other` onto the end of this list, keeping
+    // their relative order and any paths between them.
+    pub fn append(&mut self, other: QueryList<'a>) {
+        let mut new_ids = HashMap::new();
+        for qid in other.order {
+            let new_qid = self.insert_query(other.queries[&qid].clone());
+            new_ids.insert(qid, new_qid);
+        };
+
+        for (aid, target) in other.paths {
+            if let (Some(qid), Some(target)) = (new_ids.get(&aid.qid), new_ids.get(&target)) {
+                self.add_path(AnswerId { qid: *qid, sub: aid.sub }, *target);
+            };
+        };
+    }
+
     pub fn peek_queries(&self) -> &HashMap<QueryId, Query<'a>> {
         &self.queries
     }
 }
 
+// On disk, queries are an ordered list rather than a map, so ids are
+// renumbered by their position in the list order on the way out. This is the same layout that
+// `script::Script` reads.
+#[derive(Deserialize, Serialize)]
+#[serde(rename = "QueryList")]
+struct SerialQueryList<'a> {
+    #[serde(default)]
+    queries: Vec<Query<'a>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    paths: BTreeMap<AnswerId, QueryId>,
+}
+
+impl<'a> Serialize for QueryList<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ids = &self.order;
+        let position = |qid: &QueryId| ids.iter().position(|i| i == qid);
+
+        let paths = self.paths.iter()
+            .filter_map(|(aid, target)| {
+                let aid = AnswerId { qid: position(&aid.qid)?, sub: aid.sub };
+                Some((aid, position(target)?))
+            })
+            .collect();
+
+        SerialQueryList {
+            queries: ids.iter().map(|qid| self.queries[qid].clone()).collect(),
+            paths,
+        }.serialize(serializer)
+    }
+}
+
+impl<'de, 'a> Deserialize<'de> for QueryList<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let serial = SerialQueryList::deserialize(deserializer)?;
+        let mut ql = QueryList::new();
+        for q in serial.queries {
+            ql.insert_query(q);
+        };
+        for (aid, target) in serial.paths {
+            ql.add_path(aid, target);
+        };
+        Ok(ql)
+    }
+}
+
+impl<'a> Default for QueryList<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -131,6 +306,71 @@ mod test {
         let q_out = ql.get_query(qid).unwrap();
         assert_eq!(q_out, q1);
     }
+
+    #[test]
+    fn answer_id_strings() {
+        let aid: AnswerId = "q3a12".parse().unwrap();
+        assert_eq!(aid, AnswerId { qid: 3, sub: 12 });
+        assert_eq!(format!("{:?}", aid), "q3a12");
+
+        assert!("3a12".parse::<AnswerId>().is_err());
+        assert!("q3".parse::<AnswerId>().is_err());
+        assert!("qxa1".parse::<AnswerId>().is_err());
+    }
+
+    #[test]
+    fn querylist_serde() {
+        let mut ql = QueryList::new();
+        let q0 = ql.insert_query(gen_query(2));
+        let q1 = ql.insert_query(gen_query(1));
+        ql.add_path(AnswerId { qid: q0, sub: 1 }, q1);
+
+        let text = serde_json::to_string(&ql).unwrap();
+        let back: QueryList = serde_json::from_str(&text).unwrap();
+
+        assert_eq!(back.peek_queries(), ql.peek_queries());
+        assert_eq!(back.get_path(AnswerId { qid: q0, sub: 1 }), Some(q1));
+    }
+
+    #[test]
+    fn querylist_reorder() {
+        let mut ql = QueryList::new();
+        let q0 = ql.insert_query(gen_query(1));
+        let q1 = ql.insert_query(gen_query(1));
+        let q2 = ql.insert_query(gen_query(1));
+        ql.add_path(AnswerId { qid: q0, sub: 0 }, q1);
+
+        ql.move_query(q2, 0).unwrap();
+        assert_eq!(ql.order(), [q2, q0, q1]);
+        ql.move_query(q2, 10).unwrap();
+        assert_eq!(ql.order(), [q0, q1, q2]);
+        assert!(ql.move_query(99, 0).is_err());
+
+        let q3 = ql.insert_query_at(1, gen_query(1));
+        assert_eq!(ql.order(), [q0, q3, q1, q2]);
+
+        assert!(ql.remove_query(q1).is_some());
+        assert_eq!(ql.order(), [q0, q3, q2]);
+        assert_eq!(ql.get_path(AnswerId { qid: q0, sub: 0 }), None);
+        assert!(ql.remove_query(q1).is_none());
+    }
+
+    #[test]
+    fn querylist_append() {
+        let mut ql = QueryList::new();
+        ql.insert_query(gen_query(1));
+
+        let mut other = QueryList::new();
+        let o0 = other.insert_query(gen_query(2));
+        let o1 = other.insert_query(gen_query(3));
+        other.add_path(AnswerId { qid: o0, sub: 1 }, o1);
+
+        ql.append(other);
+
+        assert_eq!(ql.peek_queries().len(), 3);
+        assert_eq!(ql.get_query(2).unwrap().answers().len(), 3);
+        assert_eq!(ql.get_path(AnswerId { qid: 1, sub: 1 }), Some(2));
+    }
 }
 
 
@@ -0,0 +1,308 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{stdin, BufRead, BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+
+use crate::shell;
+
+// Puts an item into the text and commands of the queries made for it
+pub trait Fill {
+    // text that is shown or stored
+    fn text(&self, text: &str) -> String;
+    // a command line, where each thing put in has to stay one word
+    fn command(&self, cmd: &str) -> String;
+}
+
+// One item to review: a line for --each, or a JSON object or CSV row for
+// --records. Fields keep the order they were read in.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Record {
+    raw: String,
+    fields: Vec<(String, String)>,
+}
+
+impl Record {
+    // A plain line, with no fields
+    pub fn from_line(line: &str) -> Self {
+        Record {
+            raw: line.to_string(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    // Replaces `{}` with the record as it was read and `{name}` with the
+    // field of that name. Anything else in braces, like an unknown field or
+    // a `{{var.x}}` template, is left alone.
+    pub fn fill(&self, text: &str) -> String {
+        self.fill_with(text, &str::to_string)
+    }
+
+    // Like `fill`, but for a command line: what is put in is quoted, so
+    // `rm {}` removes one file whatever its name is
+    pub fn fill_command(&self, cmd: &str) -> String {
+        self.fill_with(cmd, &shell::quote)
+    }
+
+    fn fill_with(&self, text: &str, put: &dyn Fn(&str) -> String) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            if rest.starts_with("{{") {
+                let end = rest.find("}}").map_or(rest.len(), |e| e + 2);
+                out.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            };
+            let Some(end) = rest.find('}') else {
+                break;
+            };
+            let name = &rest[1..end];
+            match (name, self.get(name)) {
+                ("", _) => out.push_str(&put(&self.raw)),
+                (_, Some(value)) => out.push_str(&put(value)),
+                (_, None) => out.push_str(&rest[..=end]),
+            };
+            rest = &rest[end + 1..];
+        };
+
+        out.push_str(rest);
+        out
+    }
+
+    // The --display template used when none is given: the fields as a
+    // markdown list, or the line itself if there are none
+    pub fn default_template(&self) -> String {
+        if self.fields.is_empty() {
+            return "{}".to_string();
+        };
+        self.fields.iter()
+            .map(|(name, _)| format!("- **{}**: {{{}}}", name, name))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl Fill for Record {
+    fn text(&self, text: &str) -> String {
+        self.fill(text)
+    }
+
+    fn command(&self, cmd: &str) -> String {
+        self.fill_command(cmd)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    // one JSON object per line
+    JsonLines,
+    // a header row naming the fields, then one record per row
+    Csv,
+}
+
+impl FromStr for RecordFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "jsonl" | "json" | "ndjson" => Ok(RecordFormat::JsonLines),
+            "csv" => Ok(RecordFormat::Csv),
+            _ => bail!("Unknown record format {:?} (expected jsonl or csv)", s),
+        }
+    }
+}
+
+impl RecordFormat {
+    // Stdin, and files without a telling extension, are taken to be JSON Lines
+    pub fn from_path(path: &Path) -> RecordFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => RecordFormat::Csv,
+            _ => RecordFormat::JsonLines,
+        }
+    }
+}
+
+// Reads records from a file, or stdin if `path` is "-". Errors give the
+// path and line.
+pub fn load<P: AsRef<Path>>(path: P, format: Option<RecordFormat>) -> Result<Vec<Record>> {
+    let path = path.as_ref();
+    let format = format.unwrap_or_else(|| RecordFormat::from_path(path));
+
+    let input: Box<dyn BufRead> = if path == Path::new("-") {
+        Box::new(stdin().lock())
+    }
+    else {
+        let file = File::open(path).with_context(|| format!("{}: could not read records", path.display()))?;
+        Box::new(BufReader::new(file))
+    };
+
+    read_records(input, format).map_err(|e| anyhow!("{}: {}", path.display(), e))
+}
+
+pub fn read_records<R: BufRead>(input: R, format: RecordFormat) -> Result<Vec<Record>> {
+    match format {
+        RecordFormat::JsonLines => read_json_lines(input),
+        RecordFormat::Csv => read_csv(input),
+    }
+}
+
+fn read_json_lines<R: BufRead>(input: R) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    for (n, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        };
+        let Fields(fields) = serde_json::from_str(&line)
+            .map_err(|e| anyhow!("line {}: {}", n + 1, e))?;
+        records.push(Record { raw: line, fields });
+    };
+    Ok(records)
+}
+
+// A JSON object's fields in the order they were written. Strings are
+// taken as they are, null as nothing and anything else as JSON.
+struct Fields(Vec<(String, String)>);
+
+impl<'de> Deserialize<'de> for Fields {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FieldsVisitor;
+
+        impl<'de> Visitor<'de> for FieldsVisitor {
+            type Value = Fields;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a JSON object")
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<Fields, M::Error> {
+                let mut fields = Vec::new();
+                while let Some((name, value)) = map.next_entry::<String, serde_json::Value>()? {
+                    let value = match value {
+                        serde_json::Value::String(s) => s,
+                        serde_json::Value::Null => String::new(),
+                        v => v.to_string(),
+                    };
+                    fields.push((name, value));
+                };
+                Ok(Fields(fields))
+            }
+        }
+
+        deserializer.deserialize_map(FieldsVisitor)
+    }
+}
+
+// The header row names the fields. Rows with a different number of
+// fields than the header are an error, as is a quoted field that runs on
+// to the end of the input.
+fn read_csv<R: Read>(mut input: R) -> Result<Vec<Record>> {
+    let mut text = String::new();
+    input.read_to_string(&mut text)?;
+
+    let line_error = |e: csv::Error| match e.position() {
+        Some(pos) => anyhow!("line {}: {}", pos.line(), e),
+        None => anyhow!("{}", e),
+    };
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(text.as_bytes());
+    let header = reader.headers().map_err(line_error)?.clone();
+
+    let mut records = Vec::new();
+    let mut row = csv::StringRecord::new();
+    while reader.read_record(&mut row).map_err(line_error)? {
+        // a row starts after the end of the last one, so it takes in any
+        // blank lines in between
+        let (start, line) = row.position().map_or((0, 0), |p| (p.byte() as usize, p.line()));
+        let read = &text[start..reader.position().byte() as usize];
+        let raw = read.trim_matches(['\r', '\n']);
+        let line = line + read[..read.len() - read.trim_start_matches(['\r', '\n']).len()].matches('\n').count() as u64;
+        // quotes come in pairs, unless one was left open and swallowed the
+        // rest of the input
+        if reader.position().byte() as usize == text.len() && raw.matches('"').count() % 2 == 1 {
+            bail!("line {}: unterminated quoted field", line);
+        };
+        if row.len() != header.len() {
+            bail!("line {}: expected {} fields, found {}", line, header.len(), row.len());
+        };
+        let fields = header.iter().map(String::from).zip(row.iter().map(String::from)).collect();
+        records.push(Record { raw: raw.to_string(), fields });
+    };
+    Ok(records)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_lines() {
+        let text = r#"{"href": "https://example.com", "description": "Example", "shared": false, "tags": ["a"], "x": null}
+
+{"href": "https://weid.dev"}
+"#;
+        let records = read_records(text.as_bytes(), RecordFormat::JsonLines).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].fields()[0], ("href".to_string(), "https://example.com".to_string()));
+        assert_eq!(records[0].get("shared"), Some("false"));
+        assert_eq!(records[0].get("tags"), Some(r#"["a"]"#));
+        assert_eq!(records[0].get("x"), Some(""));
+        assert_eq!(records[1].raw(), r#"{"href": "https://weid.dev"}"#);
+
+        let err = read_records("{}\n[1, 2]\n".as_bytes(), RecordFormat::JsonLines).unwrap_err();
+        assert!(err.to_string().starts_with("line 2: "), "{}", err);
+    }
+
+    #[test]
+    fn csv() {
+        let text = "name,note\r\nweid,\"says \"\"hi\"\", twice\"\n\nbarber,\"two\nlines\"\n";
+        let records = read_records(text.as_bytes(), RecordFormat::Csv).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].get("note"), Some("says \"hi\", twice"));
+        assert_eq!(records[0].raw(), "weid,\"says \"\"hi\"\", twice\"");
+        assert_eq!(records[1].get("note"), Some("two\nlines"));
+
+        let err = read_records("a,b\n1,2\n3\n".as_bytes(), RecordFormat::Csv).unwrap_err();
+        assert_eq!(err.to_string(), "line 3: expected 2 fields, found 1");
+        let err = read_records("a\n\"open\n".as_bytes(), RecordFormat::Csv).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: unterminated quoted field");
+    }
+
+    #[test]
+    fn fill() {
+        let r = read_records(r#"{"href": "https://weid.dev", "n": 3}"#.as_bytes(), RecordFormat::JsonLines).unwrap();
+        let r = &r[0];
+        assert_eq!(r.fill("open {href} ({n})"), "open https://weid.dev (3)");
+        assert_eq!(r.fill("{missing} {{var.x}} {"), "{missing} {{var.x}} {");
+        assert_eq!(r.fill("echo '{}'"), r#"echo '{"href": "https://weid.dev", "n": 3}'"#);
+        assert_eq!(r.fill(&r.default_template()), "- **href**: https://weid.dev\n- **n**: 3");
+
+        let line = Record::from_line("notes.txt");
+        assert_eq!(line.fill("rm {} {x}"), "rm notes.txt {x}");
+        assert_eq!(line.fill(&line.default_template()), "notes.txt");
+
+        let line = Record::from_line("my notes; rm -rf ~");
+        assert_eq!(line.fill_command("rm {}"), "rm 'my notes; rm -rf ~'");
+        assert_eq!(r.fill_command("open {href} --n={n}"), "open https://weid.dev --n=3");
+    }
+}
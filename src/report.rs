@@ -0,0 +1,262 @@
+use std::io::{stderr, Write};
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+
+use crate::outcome::OutcomeResult;
+use crate::prompt::{Menu, Pick, Prompter};
+use crate::querier::Querier;
+use crate::querylist::{AnswerId, QueryId};
+use crate::vars::Vars;
+
+// An answered query, as --output reports it
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Answered {
+    pub query: QueryId,
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub answer: AnswerId,
+    // the answer as it was shown, and what it stands for
+    pub text: String,
+    pub value: String,
+    // what each of the answer's outcomes did, up to one that failed
+    pub outcomes: Vec<OutcomeResult>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    // one document once the session is over
+    Json,
+    // one line per answered query as soon as it is answered, and a last
+    // line once the session is over
+    JsonLines,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" => Ok(OutputFormat::JsonLines),
+            _ => bail!("Unknown output format {:?} (expected json or jsonl)", s),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Document<'r> {
+    answers: &'r [Answered],
+    vars: &'r Vars,
+    cancelled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// The lines of a jsonl report other than the answers themselves
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Line<'r> {
+    // an answer that was taken back by going back
+    Back {
+        answer: &'r AnswerId,
+    },
+    // the session is over; `answers` are the ones that still stand
+    End {
+        answers: Vec<AnswerId>,
+        vars: &'r Vars,
+        cancelled: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+fn write_line<W: Write, T: Serialize>(out: &mut W, line: &T) -> Result<()> {
+    serde_json::to_writer(&mut *out, line)?;
+    writeln!(out)?;
+    out.flush()?;
+    Ok(())
+}
+
+// Writes what the session did, once it is over. `error` is whatever ended
+// it early. For jsonl, the answers have been written by `Diverted` as they
+// were made, so this is just the last line.
+pub fn write<W: Write>(out: &mut W, format: OutputFormat, querier: &Querier, error: Option<&anyhow::Error>) -> Result<()> {
+    let error = error.map(|e| format!("{:#}", e));
+    match format {
+        OutputFormat::Json => {
+            let document = Document {
+                answers: &querier.report(),
+                vars: querier.vars(),
+                cancelled: querier.cancelled(),
+                error,
+            };
+            serde_json::to_writer_pretty(&mut *out, &document)?;
+            writeln!(out)?;
+            out.flush()?;
+        },
+        OutputFormat::JsonLines => {
+            let end = Line::End {
+                answers: querier.answered(),
+                vars: querier.vars(),
+                cancelled: querier.cancelled(),
+                error,
+            };
+            write_line(out, &end)?;
+        },
+    };
+    Ok(())
+}
+
+// Keeps outcome output off stdout, which the report is written to. The
+// output still goes to stderr, and is in the report as well. For jsonl,
+// each answer is written to `out` as soon as it has been made.
+pub struct Diverted<'p, W: Write> {
+    inner: Box<dyn Prompter + 'p>,
+    format: OutputFormat,
+    out: W,
+}
+
+impl<'p, W: Write> Diverted<'p, W> {
+    pub fn new(inner: Box<dyn Prompter + 'p>, format: OutputFormat, out: W) -> Self {
+        Diverted { inner, format, out }
+    }
+}
+
+impl<'p, W: Write> Prompter for Diverted<'p, W> {
+    fn ask(&mut self, menu: &Menu) -> Result<Pick> {
+        self.inner.ask(menu)
+    }
+
+    fn output(&mut self, text: &str) -> Result<()> {
+        stderr().write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    fn answered(&mut self, answered: &Answered) -> Result<()> {
+        if self.format == OutputFormat::JsonLines {
+            write_line(&mut self.out, answered)?;
+        };
+        self.inner.answered(answered)
+    }
+
+    fn took_back(&mut self, aid: &AnswerId) -> Result<()> {
+        if self.format == OutputFormat::JsonLines {
+            write_line(&mut self.out, &Line::Back { answer: aid })?;
+        };
+        self.inner.took_back(aid)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::batch::BatchPrompter;
+    use crate::outcome::Outcome;
+    use crate::qa::*;
+    use crate::querylist::QueryList;
+
+    fn queries<'a>() -> QueryList<'a> {
+        let mut q0 = Query::from_text("Deploy?".to_string());
+        q0.set_label("deploy".to_string());
+        let mut yes = Answer::from_text("yes".to_string());
+        yes.add_outcome(Outcome::new_cmd("echo deploying".to_string()));
+        yes.add_outcome(Outcome::new_set_var("deployed", true));
+        q0.add_answer(yes);
+        let mut no = Answer::from_text("no".to_string());
+        no.add_outcome(Outcome::new_cmd("weid-no-such-command".to_string()));
+        q0.add_answer(no);
+        let mut fail = Answer::from_text("fail".to_string());
+        fail.add_outcome(Outcome::new_shell("echo oops >&2; exit 3".to_string()));
+        q0.add_answer(fail);
+
+        let mut ql = QueryList::new();
+        ql.insert_query(q0);
+        ql
+    }
+
+    fn run(answers: &str) -> (Querier<'static>, Result<()>) {
+        let mut querier = Querier::new(queries());
+        querier.set_prompter(Box::new(BatchPrompter::new(vec![answers.to_string()])));
+        let result = querier.run();
+        (querier, result)
+    }
+
+    fn written(format: OutputFormat, querier: &Querier, error: Option<&anyhow::Error>) -> String {
+        let mut out = Vec::new();
+        write(&mut out, format, querier, error).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn reports() {
+        let (querier, result) = run("yes");
+        result.unwrap();
+        let document: serde_json::Value = serde_json::from_str(&written(OutputFormat::Json, &querier, None)).unwrap();
+        assert_eq!(document["vars"], serde_json::json!({"deployed": true}));
+        assert_eq!(document["cancelled"], false);
+        assert!(document.get("error").is_none());
+
+        // an outcome that fails is reported, along with what it ended
+        let (querier, result) = run("no");
+        let err = result.unwrap_err();
+        let document: serde_json::Value = serde_json::from_str(&written(OutputFormat::Json, &querier, Some(&err))).unwrap();
+        assert_eq!(document["answers"][0]["answer"], "q0a1");
+        assert_eq!(document["answers"][0]["outcomes"][0]["error"],
+            serde_json::json!({"kind": "not_found", "message": "command not found: weid-no-such-command"}));
+        assert!(document["error"].is_string());
+
+        // a command that exits with an error is reported, but goes on
+        let (querier, result) = run("fail");
+        result.unwrap();
+        let document: serde_json::Value = serde_json::from_str(&written(OutputFormat::Json, &querier, None)).unwrap();
+        let outcome = &document["answers"][0]["outcomes"][0];
+        assert_eq!((&outcome["status"], &outcome["stderr"]), (&3.into(), &"oops\n".into()));
+        assert_eq!(outcome["error"]["kind"], "status");
+
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn streams() {
+        let mut out = Vec::new();
+        let mut ql = queries();
+        let mut q1 = Query::from_text("Tag it?".to_string());
+        q1.add_answer(Answer::from_text("ok".to_string()));
+        ql.insert_query(q1);
+
+        {
+            let batch = BatchPrompter::new(["yes", "b", "no"].map(String::from).to_vec());
+            let mut querier = Querier::new(ql);
+            querier.set_prompter(Box::new(Diverted::new(Box::new(batch), OutputFormat::JsonLines, &mut out)));
+            // the answer to q0 is written before q1 is asked, and runs out
+            // of scripted answers
+            let err = querier.run().unwrap_err();
+            let end = written(OutputFormat::JsonLines, &querier, Some(&err));
+            let end: serde_json::Value = serde_json::from_str(&end).unwrap();
+            assert_eq!(end["end"]["answers"], serde_json::json!([]));
+            assert_eq!(end["end"]["cancelled"], false);
+            assert!(end["end"]["error"].is_string());
+        };
+
+        let lines: Vec<serde_json::Value> = String::from_utf8(out).unwrap().lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        let line = &lines[0];
+        assert_eq!(line["answer"], "q0a0");
+        assert_eq!((&line["label"], &line["text"], &line["value"]), (&"deploy".into(), &"yes".into(), &"yes".into()));
+        let outcomes = line["outcomes"].as_array().unwrap();
+        assert_eq!((&outcomes[0]["stdout"], &outcomes[0]["status"]), (&"deploying\n".into(), &0.into()));
+        assert!(outcomes[0]["duration"].is_f64() && outcomes[0].get("error").is_none());
+        // only commands have an exit status
+        assert!(outcomes[1].get("status").is_none());
+
+        assert_eq!(lines[1], serde_json::json!({"back": {"answer": "q0a0"}}));
+        assert_eq!(lines[2]["answer"], "q0a1");
+        assert_eq!(lines[2]["outcomes"][0]["error"]["kind"], "not_found");
+    }
+}
@@ -0,0 +1,357 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{stdout, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::qa::*;
+use crate::querier::Fallback;
+use crate::querylist::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptFormat {
+    Toml,
+    Json,
+}
+
+impl ScriptFormat {
+    pub fn from_path(path: &Path) -> Result<ScriptFormat> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Ok(ScriptFormat::Toml),
+            Some("json") => Ok(ScriptFormat::Json),
+            _ => bail!("{}: unknown script format (expected .toml or .json)", path.display()),
+        }
+    }
+}
+
+// A whole session described declaratively. Default answers are added to
+// every query, the same way leading `-a` flags are on the command line.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Script<'a> {
+    #[serde(default)]
+    answers: Vec<Answer<'a>>,
+    #[serde(default)]
+    queries: Vec<Query<'a>>,
+    #[serde(default)]
+    paths: BTreeMap<AnswerId, QueryId>,
+    // what to do after an answer without a path, like --fallback
+    fallback: Option<Fallback>,
+}
+
+impl<'a> Script<'a> {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Script<'a>> {
+        let path = path.as_ref();
+        let format = ScriptFormat::from_path(path)?;
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("{}: could not read script", path.display()))?;
+
+        Script::parse(&text, format).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str, format: ScriptFormat) -> Result<Script<'a>> {
+        let script: Script = match format {
+            ScriptFormat::Toml => {
+                let de = toml::Deserializer::new(text);
+                serde_path_to_error::deserialize(de).map_err(|e| {
+                    let line = e.inner().span().map(|s| line_of(text, s.start));
+                    script_error(line, e.path(), e.inner().message())
+                })
+            },
+            ScriptFormat::Json => {
+                let mut de = serde_json::Deserializer::from_str(text);
+                serde_path_to_error::deserialize(&mut de).map_err(|e| {
+                    let line = Some(e.inner().line()).filter(|l| *l > 0);
+                    script_error(line, e.path(), &e.inner().to_string())
+                })
+            },
+        }?;
+        script.check_paths()?;
+        Ok(script)
+    }
+
+    // Paths refer to queries by their position in the file and to answers
+    // by their position in the query, after the default answers. A path
+    // that points at nothing would otherwise be dropped without a word.
+    fn check_paths(&self) -> Result<()> {
+        let queries = self.queries.len();
+        for (aid, target) in &self.paths {
+            let Some(query) = self.queries.get(aid.qid()) else {
+                bail!("field `paths.{:?}`: there is no query {} (the file has {})", aid, aid.qid(), queries);
+            };
+            let answers = self.answers.len() + query.answers().len();
+            if aid.sub() >= answers {
+                bail!("field `paths.{:?}`: query {} has no answer {} (it has {})", aid, aid.qid(), aid.sub(), answers);
+            };
+            if *target >= queries {
+                bail!("field `paths.{:?}`: there is no query {} to go to (the file has {})", aid, target, queries);
+            };
+        };
+        Ok(())
+    }
+
+    pub fn fallback(&self) -> Option<Fallback> {
+        self.fallback
+    }
+
+    pub fn queries(&self) -> Vec<Query<'a>> {
+        self.queries.iter().map(|q| {
+            let mut query = q.clone();
+            query.prepend_answers(self.answers.clone());
+            query
+        }).collect()
+    }
+
+    pub fn to_querylist(&self) -> QueryList<'a> {
+        let mut ql = QueryList::new();
+        for q in self.queries() {
+            ql.insert_query(q);
+        };
+        for (aid, target) in self.paths.iter() {
+            ql.add_path(aid.clone(), *target);
+        };
+        ql
+    }
+}
+
+// What gets written: the queries, and the fallback unless it is the default
+#[derive(Serialize)]
+struct Dump<'q, 'a> {
+    #[serde(skip_serializing_if = "is_default_fallback")]
+    fallback: Fallback,
+    #[serde(flatten)]
+    ql: &'q QueryList<'a>,
+}
+
+fn is_default_fallback(fallback: &Fallback) -> bool {
+    *fallback == Fallback::default()
+}
+
+pub fn to_string(ql: &QueryList, fallback: Fallback, format: ScriptFormat) -> Result<String> {
+    let dump = Dump { fallback, ql };
+    let field_error = |path: &serde_path_to_error::Path, msg: String| {
+        match path.to_string().as_str() {
+            "." => anyhow!("{}", msg),
+            f => anyhow!("field `{}`: {}", f, msg),
+        }
+    };
+
+    match format {
+        ScriptFormat::Toml => {
+            let mut out = String::new();
+            serde_path_to_error::serialize(&dump, toml::Serializer::pretty(&mut out))
+                .map_err(|e| field_error(e.path(), e.inner().to_string()))?;
+            Ok(out)
+        },
+        ScriptFormat::Json => {
+            let mut out = Vec::new();
+            let mut ser = serde_json::Serializer::pretty(&mut out);
+            serde_path_to_error::serialize(&dump, &mut ser)
+                .map_err(|e| field_error(e.path(), e.inner().to_string()))?;
+            out.push(b'\n');
+            Ok(String::from_utf8(out)?)
+        },
+    }
+}
+
+// Writes `ql` and `fallback` as a script file, picking the format from the
+// extension. A path of "-" writes TOML to stdout.
+pub fn dump<P: AsRef<Path>>(ql: &QueryList, fallback: Fallback, path: P) -> Result<()> {
+    let path = path.as_ref();
+    if path == Path::new("-") {
+        let text = to_string(ql, fallback, ScriptFormat::Toml)?;
+        stdout().write_all(text.as_bytes())?;
+        return Ok(());
+    };
+
+    let text = to_string(ql, fallback, ScriptFormat::from_path(path)?)
+        .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    fs::write(path, text).with_context(|| format!("{}: could not write script", path.display()))
+}
+
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+fn script_error(line: Option<usize>, path: &serde_path_to_error::Path, msg: &str) -> anyhow::Error {
+    // serde_json appends its own position to the message; drop it since we
+    // report the line ourselves
+    let msg = msg.split(" at line ").next().unwrap_or(msg).trim();
+    let field = path.to_string();
+
+    match (line, field.as_str()) {
+        (Some(l), ".") => anyhow!("line {}: {}", l, msg),
+        (Some(l), f) => anyhow!("line {}, field `{}`: {}", l, f, msg),
+        (None, ".") => anyhow!("{}", msg),
+        (None, f) => anyhow!("field `{}`: {}", f, msg),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::outcome::Outcome;
+
+    const TOML_SCRIPT: &str = r#"
+fallback = "stop"
+
+[[answers]]
+text = "spicy!"
+
+[[queries]]
+text = "How are you feeling?"
+key = "mood"
+
+[[queries.answers]]
+text = "good"
+value = "ok"
+
+[[queries.answers]]
+text = "bad"
+outcomes = [{ command = "ls" }]
+exit = 1
+
+[[queries.answers]]
+text = "worse"
+outcomes = [
+    { undoable = { run = { command = "touch x" }, undo = { command = "rm x" } } },
+    { set_var = { name = "mood", value = "worse" } },
+    { compute = { name = "score", expr = "score - 1" } },
+    { shell = "ls | wc -l" },
+]
+
+[[queries]]
+text = "What rhymes with klicy?"
+if = "mood == 'worse'"
+"#;
+
+    #[test]
+    fn toml_script() {
+        let script = Script::parse(TOML_SCRIPT, ScriptFormat::Toml).unwrap();
+
+        let correct = {
+            let spicy = Answer::from_text("spicy!".to_string());
+            let mut good = Answer::from_text("good".to_string());
+            good.set_value("ok".to_string());
+            let mut bad = Answer::from_text("bad".to_string());
+            bad.add_outcome(Outcome::Command("ls".to_string()));
+            let worse = Answer::from_text("worse".to_string());
+
+            let mut q1 = Query::from_text("How are you feeling?".to_string());
+            q1.set_key("mood".to_string());
+            q1.add_answers(Vec::from([spicy.clone(), good, bad, worse]));
+            let mut q2 = Query::from_text("What rhymes with klicy?".to_string());
+            q2.add_answer(spicy);
+
+            Vec::from([q1, q2])
+        };
+
+        let queries = script.queries();
+        assert_eq!(queries, correct);
+        assert_eq!(queries[0].answers(), correct[0].answers());
+        assert_eq!(queries[1].answers(), correct[1].answers());
+        assert!(queries[0].answers()[3].outcomes()[0].undo().is_some());
+        assert!(matches!(&queries[0].answers()[3].outcomes()[1], Outcome::SetVar { .. }));
+        assert!(matches!(&queries[0].answers()[3].outcomes()[2], Outcome::Compute { .. }));
+        assert!(matches!(&queries[0].answers()[3].outcomes()[3], Outcome::Shell(c) if c == "ls | wc -l"));
+        assert_eq!(queries[1].guard().unwrap().source(), "mood == 'worse'");
+        assert_eq!(queries[0].answers()[2].exit_code(), Some(1));
+        assert_eq!(queries[0].answers()[1].exit_code(), None);
+        assert_eq!(queries[0].key(), Some(&"mood".to_string()));
+        assert_eq!(script.fallback(), Some(Fallback::Stop));
+        assert_eq!((queries[0].answers()[1].display(), queries[0].answers()[1].value()), ("good".to_string(), "ok".to_string()));
+    }
+
+    #[test]
+    fn json_script() {
+        let text = r#"{"queries": [{"text": "q1", "answers": [{"text": "a1"}]}]}"#;
+        let ql = Script::parse(text, ScriptFormat::Json).unwrap().to_querylist();
+
+        let mut q1 = Query::from_text("q1".to_string());
+        q1.add_answer(Answer::from_text("a1".to_string()));
+        assert_eq!(ql.get_query(0).unwrap(), q1);
+    }
+
+    #[test]
+    fn round_trip() {
+        let script = Script::parse(TOML_SCRIPT, ScriptFormat::Toml).unwrap();
+        let ql = script.to_querylist();
+
+        for format in [ScriptFormat::Toml, ScriptFormat::Json] {
+            let text = to_string(&ql, script.fallback().unwrap(), format).unwrap();
+            let back = Script::parse(&text, format).unwrap();
+            assert_eq!(back.fallback(), Some(Fallback::Stop));
+            let back = back.to_querylist();
+            assert_eq!(back.peek_queries(), ql.peek_queries());
+            assert_eq!(back.get_query(0).unwrap().answers(), ql.get_query(0).unwrap().answers());
+            assert_eq!(
+                back.get_query(0).unwrap().answers()[2].outcomes().len(),
+                ql.get_query(0).unwrap().answers()[2].outcomes().len(),
+            );
+            assert!(back.get_query(0).unwrap().answers()[3].outcomes()[0].undo().is_some());
+            assert!(matches!(
+                &back.get_query(0).unwrap().answers()[3].outcomes()[1],
+                Outcome::SetVar { value: crate::vars::Value::Str(v), .. } if v == "worse"
+            ));
+            assert!(matches!(
+                &back.get_query(0).unwrap().answers()[3].outcomes()[2],
+                Outcome::Compute { name, expr } if name == "score" && expr.source() == "score - 1"
+            ));
+            assert!(matches!(&back.get_query(0).unwrap().answers()[3].outcomes()[3], Outcome::Shell(c) if c == "ls | wc -l"));
+            assert_eq!(back.get_query(1).unwrap().guard(), ql.get_query(1).unwrap().guard());
+            assert_eq!(back.get_query(0).unwrap().answers()[2].exit_code(), Some(1));
+            assert_eq!(back.get_query(0).unwrap().key(), Some(&"mood".to_string()));
+            assert_eq!(back.get_query(0).unwrap().answers()[1].value(), "ok");
+        };
+    }
+
+    #[test]
+    fn unserializable_outcomes() {
+        let mut a = Answer::from_text("a1".to_string());
+        a.add_outcome(Outcome::new_closure(|| Ok("".to_string())));
+        let mut q = Query::from_text("q1".to_string());
+        q.add_answer(a);
+        let mut ql = QueryList::new();
+        ql.insert_query(q);
+
+        let err = to_string(&ql, Fallback::Next, ScriptFormat::Json).unwrap_err().to_string();
+        assert_eq!(err, "field `queries[0].answers[0].outcomes[0]`: Closure outcomes are not serializable");
+    }
+
+    #[test]
+    fn script_errors() {
+        let text = "[[queries]]\ntext = \"q1\"\n\n[[queries.answers]]\ntxt = \"a1\"\n";
+        let err = Script::parse(text, ScriptFormat::Toml).unwrap_err().to_string();
+        assert!(err.starts_with("line 5, field `queries[0].answers[0].txt`"), "{}", err);
+
+        let text = "{\n\"queries\": [\n{\"text\": 3}\n]\n}";
+        let err = Script::parse(text, ScriptFormat::Json).unwrap_err().to_string();
+        assert!(err.starts_with("line 3, field `queries[0].text`"), "{}", err);
+
+        let text = "[[queries]]\ntext = \"q1\"\nif = \"visited(\"\n";
+        let err = Script::parse(text, ScriptFormat::Toml).unwrap_err().to_string();
+        assert!(err.contains("field `queries[0].if`") && err.contains("at position 8"), "{}", err);
+
+        let text = "[[queries]]\ntext = \"q1\"\nweight = nan\n";
+        let err = Script::parse(text, ScriptFormat::Toml).unwrap_err().to_string();
+        assert!(err.starts_with("line 3, field `queries[0].weight`: weight NaN"), "{}", err);
+
+        for (code, problem) in [(130, "is kept for weid's own use"), (300, "is not between 0 and 255")] {
+            let text = format!("[[queries]]\ntext = \"q1\"\n[[queries.answers]]\ntext = \"a1\"\nexit = {}\n", code);
+            let err = Script::parse(&text, ScriptFormat::Toml).unwrap_err().to_string();
+            assert!(err.starts_with("line 5, field `queries[0].answers[0].exit`: exit code") && err.contains(problem), "{}", err);
+        };
+
+        let text = "[[answers]]\ntext = \"ok\"\n[[queries]]\ntext = \"q0\"\n[[queries.answers]]\ntext = \"a0\"\n[paths]\n";
+        assert!(Script::parse(&format!("{}q0a1 = 0\n", text), ScriptFormat::Toml).is_ok());
+        let err = Script::parse(&format!("{}q0a2 = 0\n", text), ScriptFormat::Toml).unwrap_err().to_string();
+        assert_eq!(err, "field `paths.q0a2`: query 0 has no answer 2 (it has 2)");
+        let err = Script::parse(&format!("{}q1a0 = 0\n", text), ScriptFormat::Toml).unwrap_err().to_string();
+        assert_eq!(err, "field `paths.q1a0`: there is no query 1 (the file has 1)");
+        let err = Script::parse(&format!("{}q0a0 = 3\n", text), ScriptFormat::Toml).unwrap_err().to_string();
+        assert_eq!(err, "field `paths.q0a0`: there is no query 3 to go to (the file has 1)");
+    }
+}
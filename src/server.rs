@@ -0,0 +1,413 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::fifo;
+use crate::outcome::Outcome;
+use crate::headless::{QueryView, Selection};
+use crate::prompt::{Menu, Pick, Prompter};
+use crate::querier::Querier;
+use crate::querylist::{AnswerId, QueryId};
+use crate::vars::Vars;
+
+// JSON-RPC error codes
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+// an outcome failed, or the session couldn't do what was asked
+pub const SESSION_ERROR: i64 = -32000;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into() }
+    }
+}
+
+// Everything a client needs to draw the session
+#[derive(Debug, Serialize)]
+pub struct State<'s> {
+    // the query waiting for an answer, if there is one
+    query: Option<QueryView<'s>>,
+    answers: Vec<AnswerId>,
+    vars: &'s Vars,
+}
+
+// Passes what outcomes print on to the clients, rather than the terminal.
+// Queries are answered by requests, so it is never asked anything.
+struct Outputs(Rc<RefCell<Vec<String>>>);
+
+impl Prompter for Outputs {
+    fn ask(&mut self, _menu: &Menu) -> Result<Pick> {
+        bail!("queries are answered by clients of the server")
+    }
+
+    fn output(&mut self, text: &str) -> Result<()> {
+        self.0.borrow_mut().push(text.to_string());
+        Ok(())
+    }
+}
+
+// A session driven by requests rather than a prompter. Rather than asking
+// a query and waiting, the query is kept as `current` until a request
+// answers it.
+pub struct Session<'q, 'a> {
+    querier: &'q mut Querier<'a>,
+    current: Option<QueryId>,
+    outputs: Rc<RefCell<Vec<String>>>,
+}
+
+impl<'q, 'a> Session<'q, 'a> {
+    pub fn new(querier: &'q mut Querier<'a>) -> Self {
+        let outputs = Rc::new(RefCell::new(Vec::new()));
+        querier.set_prompter(Box::new(Outputs(outputs.clone())));
+        let mut session = Session { querier, current: None, outputs };
+        session.advance();
+        session
+    }
+
+    // Moves on to the next query, if nothing is waiting for an answer
+    fn advance(&mut self) {
+        if self.current.is_none() {
+            self.current = self.querier.pick_next_query();
+            if let Some(qid) = self.current {
+                self.querier.mark_visited(qid);
+            };
+        };
+    }
+
+    fn menu(&self) -> Result<Option<Menu>, RpcError> {
+        self.current.map(|qid| self.querier.menu(qid))
+            .transpose()
+            .map_err(|e| RpcError::new(SESSION_ERROR, format!("{:#}", e)))
+    }
+
+    pub fn state(&self) -> Result<Value, RpcError> {
+        let menu = self.menu()?;
+        let state = State {
+            query: menu.as_ref().map(QueryView::new),
+            answers: self.querier.answered(),
+            vars: self.querier.vars(),
+        };
+        Ok(serde_json::to_value(state).unwrap())
+    }
+
+    // Whatever outcomes have printed since last time
+    pub fn take_outputs(&mut self) -> Vec<String> {
+        self.outputs.borrow_mut().drain(..).collect()
+    }
+
+    // Runs a method. Returns its result, and whether the session changed.
+    pub fn call(&mut self, method: &str, params: Value) -> Result<(Value, bool), RpcError> {
+        let session_error = |e: anyhow::Error| RpcError::new(SESSION_ERROR, format!("{:#}", e));
+
+        match method {
+            "current_query" => {
+                let menu = self.menu()?;
+                Ok((serde_json::to_value(menu.as_ref().map(QueryView::new)).unwrap(), false))
+            },
+            "get_state" => Ok((self.state()?, false)),
+            "answer" => {
+                let selection: Selection = serde_json::from_value(params.get("answer").cloned().unwrap_or_default())
+                    .map_err(|_| RpcError::new(INVALID_PARAMS, "expected {\"answer\": <position or id>}"))?;
+                let Some(menu) = self.menu()? else {
+                    return Err(RpcError::new(SESSION_ERROR, "there is no query to answer"));
+                };
+                let i = selection.position(&menu).map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+                self.querier.choose_answer(menu.answer_ids[i].clone()).map_err(session_error)?;
+                self.current = None;
+                self.advance();
+                Ok((self.state()?, true))
+            },
+            "back" => {
+                if !self.querier.can_go_back() {
+                    return Err(RpcError::new(SESSION_ERROR, "there is nothing to go back to"));
+                };
+                if let Some(qid) = self.current.take() {
+                    self.querier.unvisit(qid);
+                };
+                self.querier.back().map_err(session_error)?;
+                self.advance();
+                Ok((self.state()?, true))
+            },
+            "add_query" => {
+                let ql = fifo::parse(&params.to_string())
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, format!("{:#}", e)))?;
+                // anyone who can connect could otherwise run whatever they
+                // like, by adding a command and then answering it
+                let runs_program = ql.order().iter()
+                    .filter_map(|qid| ql.get_query(*qid))
+                    .flat_map(|q| q.answers())
+                    .any(|a| a.outcomes().iter().any(Outcome::runs_program));
+                if runs_program {
+                    return Err(RpcError::new(INVALID_PARAMS, "added queries can't run commands"));
+                };
+                self.querier.append_queries(ql);
+                self.advance();
+                Ok((self.state()?, true))
+            },
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("no method {:?}", method))),
+        }
+    }
+
+    // Handles one line of JSON-RPC 2.0. Returns the response, unless the
+    // request was a notification, along with the notifications every
+    // client should get about it.
+    pub fn handle(&mut self, line: &str) -> (Option<Value>, Vec<Value>) {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => return (Some(error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string()))), Vec::new()),
+        };
+        let id = request.get("id").cloned();
+        let Some(method) = request.get("method").and_then(|m| m.as_str()) else {
+            let error = RpcError::new(INVALID_REQUEST, "expected a \"method\"");
+            return (Some(error_response(id.unwrap_or_default(), error)), Vec::new());
+        };
+        let params = request.get("params").cloned().unwrap_or(json!({}));
+
+        let result = self.call(method, params);
+
+        let mut notifications: Vec<Value> = self.take_outputs().into_iter()
+            .map(|text| notification("output", json!({ "text": text })))
+            .collect();
+        if let Ok((state, true)) = &result {
+            notifications.push(notification("state", state.clone()));
+        };
+
+        let response = id.map(|id| match result {
+            Ok((value, _)) => json!({ "jsonrpc": "2.0", "id": id, "result": value }),
+            Err(error) => error_response(id, error),
+        });
+        (response, notifications)
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": error })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+enum Event {
+    Connected(usize, Box<dyn Write + Send>),
+    Request(usize, String),
+    Disconnected(usize),
+}
+
+// Reads a client's requests on a thread of its own
+fn connect<S>(id: usize, stream: S, writer: Box<dyn Write + Send>, tx: &Sender<Event>)
+where
+    S: std::io::Read + Send + 'static,
+{
+    if tx.send(Event::Connected(id, writer)).is_err() {
+        return;
+    };
+    let tx = tx.clone();
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else { break };
+            if tx.send(Event::Request(id, line)).is_err() {
+                return;
+            };
+        };
+        let _ = tx.send(Event::Disconnected(id));
+    });
+}
+
+// A Unix socket path, or a host:port on this machine
+fn listen(address: &str, tx: Sender<Event>) -> Result<()> {
+    if address.contains('/') || !address.contains(':') {
+        let path = Path::new(address);
+        // a socket left behind by an earlier server is in the way, but one
+        // that still answers belongs to a server that is running
+        if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            if UnixStream::connect(path).is_ok() {
+                bail!("{}: already being served", address);
+            };
+            fs::remove_file(path)?;
+        };
+        let listener = UnixListener::bind(path).with_context(|| format!("{}: could not listen", address))?;
+        // only for the user running weid, since answering runs commands
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        thread::spawn(move || {
+            for (id, stream) in listener.incoming().flatten().enumerate() {
+                let Ok(writer) = stream.try_clone() else { continue };
+                connect(id, stream, Box::new(writer), &tx);
+            };
+        });
+    }
+    else {
+        let addrs: Vec<_> = address.to_socket_addrs().with_context(|| format!("{}: bad address", address))?.collect();
+        // answering queries runs commands, so only local clients are let in
+        if let Some(addr) = addrs.iter().find(|a| !a.ip().is_loopback()) {
+            bail!("{}: only localhost addresses can be served ({} is not one)", address, addr.ip());
+        };
+        let listener = TcpListener::bind(&addrs[..]).with_context(|| format!("{}: could not listen", address))?;
+        thread::spawn(move || {
+            for (id, stream) in listener.incoming().flatten().enumerate() {
+                let Ok(writer) = stream.try_clone() else { continue };
+                connect(id, stream, Box::new(writer), &tx);
+            };
+        });
+    };
+    Ok(())
+}
+
+// Serves a session on `address` until the process is stopped. Clients
+// send JSON-RPC 2.0 requests, one per line. Every client is told about
+// changes, whoever made them, with `state` and `output` notifications.
+pub fn serve(querier: &mut Querier, address: &str) -> Result<()> {
+    let (tx, rx) = channel();
+    listen(address, tx)?;
+    eprintln!("weid: serving on {}", address);
+
+    let mut session = Session::new(querier);
+    let mut clients: BTreeMap<usize, Box<dyn Write + Send>> = BTreeMap::new();
+
+    for event in rx.iter() {
+        match event {
+            Event::Connected(id, writer) => {
+                clients.insert(id, writer);
+            },
+            Event::Disconnected(id) => {
+                clients.remove(&id);
+            },
+            Event::Request(id, line) => {
+                if line.trim().is_empty() {
+                    continue;
+                };
+                let (response, notifications) = session.handle(&line);
+                if let Some(response) = response {
+                    if let Some(client) = clients.get_mut(&id) {
+                        if writeln!(client, "{}", response).is_err() {
+                            clients.remove(&id);
+                        };
+                    };
+                };
+                for message in notifications {
+                    clients.retain(|_, client| writeln!(client, "{}", message).is_ok());
+                };
+            },
+        };
+    };
+
+    Err(anyhow!("{}: stopped listening", address))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::qa::*;
+    use crate::querylist::QueryList;
+
+    fn querier<'a>() -> Querier<'a> {
+        let mut q0 = Query::from_text("Deploy?".to_string());
+        let mut yes = Answer::from_text("yes".to_string());
+        yes.add_outcome(Outcome::new_cmd("echo deploying".to_string()));
+        q0.add_answer(yes);
+        q0.add_answer(Answer::from_text("no".to_string()));
+        let mut q1 = Query::from_text("Tag it?".to_string());
+        q1.add_answer(Answer::from_text("ok".to_string()));
+
+        let mut ql = QueryList::new();
+        ql.insert_query(q0);
+        ql.insert_query(q1);
+        Querier::new(ql)
+    }
+
+    fn call(session: &mut Session, request: &str) -> Value {
+        session.handle(request).0.unwrap()
+    }
+
+    #[test]
+    fn session() {
+        let mut querier = querier();
+        let mut session = Session::new(&mut querier);
+
+        let current = call(&mut session, r#"{"jsonrpc": "2.0", "id": 1, "method": "current_query"}"#);
+        assert_eq!(current["id"], 1);
+        assert_eq!(current["result"]["text"], "Deploy?");
+        assert_eq!(current["result"]["answers"][1], json!({"id": "q0a1", "text": "no"}));
+
+        let (response, notifications) = session.handle(r#"{"jsonrpc": "2.0", "id": 2, "method": "answer", "params": {"answer": 0}}"#);
+        assert_eq!(response.unwrap()["result"]["query"]["text"], "Tag it?");
+        assert_eq!(notifications[0], json!({"jsonrpc": "2.0", "method": "output", "params": {"text": "deploying\n"}}));
+        assert_eq!(notifications[1]["method"], "state");
+        assert_eq!(notifications[1]["params"]["answers"], json!(["q0a0"]));
+
+        let back = call(&mut session, r#"{"jsonrpc": "2.0", "id": 3, "method": "back"}"#);
+        assert_eq!(back["result"]["query"]["text"], "Deploy?");
+        assert_eq!(back["result"]["answers"], json!([]));
+
+        call(&mut session, r#"{"jsonrpc": "2.0", "id": 4, "method": "answer", "params": {"answer": "q0a1"}}"#);
+        let done = call(&mut session, r#"{"jsonrpc": "2.0", "id": 5, "method": "answer", "params": {"answer": 0}}"#);
+        assert_eq!(done["result"]["query"], Value::Null);
+        assert_eq!(done["result"]["answers"], json!(["q0a1", "q1a0"]));
+
+        // queries added once the session has run out are asked straight away
+        let added = call(&mut session, r#"{"jsonrpc": "2.0", "id": 6, "method": "add_query", "params": {"text": "Again?", "answers": [{"text": "no"}]}}"#);
+        assert_eq!(added["result"]["query"]["text"], "Again?");
+        let state = call(&mut session, r#"{"jsonrpc": "2.0", "id": 7, "method": "get_state"}"#);
+        assert_eq!(state["result"]["query"]["id"], 2);
+    }
+
+    #[test]
+    fn socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("weid.sock");
+        let address = path.display().to_string();
+
+        // left behind, with nothing listening
+        drop(UnixListener::bind(&path).unwrap());
+        listen(&address, channel().0).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let err = listen(&address, channel().0).unwrap_err();
+        assert!(err.to_string().contains("already being served"), "{}", err);
+    }
+
+    #[test]
+    fn errors() {
+        let mut querier = querier();
+        let mut session = Session::new(&mut querier);
+        let code = |session: &mut Session, request: &str| call(session, request)["error"]["code"].clone();
+
+        assert_eq!(code(&mut session, "{"), PARSE_ERROR);
+        assert_eq!(code(&mut session, r#"{"jsonrpc": "2.0", "id": 1}"#), INVALID_REQUEST);
+        assert_eq!(code(&mut session, r#"{"jsonrpc": "2.0", "id": 1, "method": "quit"}"#), METHOD_NOT_FOUND);
+        assert_eq!(code(&mut session, r#"{"jsonrpc": "2.0", "id": 1, "method": "answer", "params": {"answer": 5}}"#), INVALID_PARAMS);
+        assert_eq!(code(&mut session, r#"{"jsonrpc": "2.0", "id": 1, "method": "answer"}"#), INVALID_PARAMS);
+        assert_eq!(code(&mut session, r#"{"jsonrpc": "2.0", "id": 1, "method": "add_query", "params": {"txt": "x"}}"#), INVALID_PARAMS);
+        assert_eq!(code(&mut session, r#"{"jsonrpc": "2.0", "id": 1, "method": "back"}"#), SESSION_ERROR);
+        for outcome in [r#"{"command": "rm -rf ~"}"#, r#"{"shell": "rm -rf ~"}"#, r#"{"undoable": {"run": {"set_var": {"name": "x", "value": 1}}, "undo": {"command": "rm -rf ~"}}}"#] {
+            let request = format!(r#"{{"jsonrpc": "2.0", "id": 1, "method": "add_query", "params": {{"text": "x", "answers": [{{"text": "y", "outcomes": [{}]}}]}}}}"#, outcome);
+            assert_eq!(code(&mut session, &request), INVALID_PARAMS, "{}", outcome);
+        };
+
+        // notifications get no response
+        let (response, _) = session.handle(r#"{"jsonrpc": "2.0", "method": "answer", "params": {"answer": 1}}"#);
+        assert!(response.is_none());
+        assert_eq!(session.state().unwrap()["answers"], json!(["q0a1"]));
+    }
+}
@@ -0,0 +1,159 @@
+use std::io;
+use std::process::{Command, Output};
+
+use anyhow::{Result, bail};
+
+use crate::outcome::{ErrorKind, OutcomeError};
+
+// Splits a command line into words the way a POSIX shell would, without
+// expanding anything: whitespace separates words, '...' keeps everything
+// as it is, "..." keeps everything but `\"`, `\\`, `\$` and `` \` ``, and
+// a backslash outside quotes keeps the next character as it is.
+pub fn split(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                words.extend(word.take());
+            },
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => w.push(c),
+                        None => bail!("unterminated ' in {:?}", line),
+                    };
+                };
+            },
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => w.push(c),
+                            Some('\n') => {},
+                            Some(c) => {
+                                w.push('\\');
+                                w.push(c);
+                            },
+                            None => bail!("unterminated \" in {:?}", line),
+                        },
+                        Some(c) => w.push(c),
+                        None => bail!("unterminated \" in {:?}", line),
+                    };
+                };
+            },
+            '\\' => match chars.next() {
+                Some('\n') => {},
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => bail!("trailing \\ in {:?}", line),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        };
+    };
+
+    words.extend(word);
+    Ok(words)
+}
+
+// Quotes a word so that `split`, or a shell, reads it back as one word
+// with the same text. Words that need no quoting are left alone.
+pub fn quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-+=.,/:@%".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        return word.to_string();
+    };
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+fn not_found(program: &str, e: io::Error) -> OutcomeError {
+    match e.kind() {
+        io::ErrorKind::NotFound => OutcomeError::new(ErrorKind::NotFound, format!("command not found: {}", program)),
+        _ => OutcomeError::new(ErrorKind::Spawn, format!("could not run {}: {}", program, e)),
+    }
+}
+
+fn empty() -> OutcomeError {
+    OutcomeError::new(ErrorKind::BadCommand, "empty command")
+}
+
+// Runs a command line without a shell: the words are passed to the program
+// as they are, so pipes and redirects are just more arguments
+pub fn run(line: &str) -> Result<Output> {
+    let words = split(line).map_err(|e| OutcomeError::new(ErrorKind::BadCommand, e.to_string()))?;
+    let Some((program, args)) = words.split_first() else {
+        bail!(empty());
+    };
+    Ok(Command::new(program).args(args).output().map_err(|e| not_found(program, e))?)
+}
+
+// Runs a command line with `sh -c`, for pipes, redirects and the like
+pub fn run_sh(line: &str) -> Result<Output> {
+    if line.trim().is_empty() {
+        bail!(empty());
+    };
+    Ok(Command::new("sh").arg("-c").arg(line).output().map_err(|e| not_found("sh", e))?)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::outcome::OutcomeResult;
+
+    #[test]
+    fn splitting() {
+        let words = |line: &str| split(line).unwrap();
+        assert_eq!(words("git  branch -D\tfeature"), ["git", "branch", "-D", "feature"]);
+        assert_eq!(words(r#"echo 'a  b' "c \"d\" \$e \n" f\ g ''"#), ["echo", "a  b", r#"c "d" $e \n"#, "f g", ""]);
+        assert_eq!(words(r#"grep -e"x y"z"#), ["grep", "-ex yz"]);
+        assert_eq!(words("ls | wc -l > out"), ["ls", "|", "wc", "-l", ">", "out"]);
+        assert!(words("  ").is_empty());
+
+        assert!(split("echo 'open").is_err());
+        assert!(split("echo \"open").is_err());
+        assert!(split("echo \\").is_err());
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote("notes.txt"), "notes.txt");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("it's a file; rm -rf ~"), r"'it'\''s a file; rm -rf ~'");
+        for word in ["a b", "it's", "$HOME", "\"q\"", "x\\y", "line\nbreak"] {
+            assert_eq!(split(&format!("echo {}", quote(word))).unwrap(), ["echo", word]);
+        };
+    }
+
+    #[test]
+    fn running() {
+        let out = run("printf '%s|' 'a  b' c").unwrap();
+        assert_eq!(String::from_utf8(out.stdout).unwrap(), "a  b|c|");
+        let out = run_sh("printf 'a b' | tr ' ' _").unwrap();
+        assert_eq!(String::from_utf8(out.stdout).unwrap(), "a_b");
+
+        assert_eq!(run("weid-no-such-command x").unwrap_err().to_string(), "command not found: weid-no-such-command");
+        assert_eq!(run("").unwrap_err().to_string(), "empty command");
+        assert_eq!(run_sh(" ").unwrap_err().to_string(), "empty command");
+
+        let kind = |e: anyhow::Error| OutcomeError::of(&e).kind;
+        assert_eq!(kind(run("weid-no-such-command").unwrap_err()), ErrorKind::NotFound);
+        assert_eq!(kind(run("echo 'open").unwrap_err()), ErrorKind::BadCommand);
+        assert_eq!(kind(run("/").unwrap_err()), ErrorKind::Spawn);
+
+        // a shell that couldn't find a command still ran, and says so
+        for line in ["weid-no-such-command x", "exit 127"] {
+            let result = OutcomeResult::from_sh_output(run_sh(line).unwrap());
+            assert_eq!(result.status, Some(127), "{}", line);
+            assert_eq!(result.error.unwrap().kind, ErrorKind::NotFound, "{}", line);
+        };
+        let result = OutcomeResult::from_sh_output(run_sh("echo out; weid-no-such-command").unwrap());
+        assert_eq!(result.stdout, "out\n");
+        assert!(result.stderr.contains("weid-no-such-command"));
+    }
+}
@@ -0,0 +1,65 @@
+use crate::expr::{Env, Expr};
+use crate::vars::Value;
+
+// Expands the `{{...}}` placeholders in query and answer text:
+//
+//   {{answer.LABEL}}  the answer most recently given to a query
+//   {{var.NAME}}      a session variable
+//   {{env.NAME}}      an environment variable
+//   {{EXPR}}          anything else is evaluated as an expression
+//
+// Placeholders with no value expand to nothing. Ones that can't be read
+// at all are left in place, so a typo shows up on screen rather than
+// quietly disappearing.
+pub fn render(text: &str, env: &dyn Env) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let end = start + 2 + len + 2;
+        out.push_str(&rest[..start]);
+        match expand(rest[start + 2..end - 2].trim(), env) {
+            Some(v) => out.push_str(&v.map(|v| v.to_string()).unwrap_or_default()),
+            None => out.push_str(&rest[start..end]),
+        };
+        rest = &rest[end..];
+    };
+
+    out.push_str(rest);
+    out
+}
+
+// None if the placeholder doesn't make sense, Some(None) if it does but
+// has no value right now
+fn expand(inner: &str, env: &dyn Env) -> Option<Option<Value>> {
+    let name_like = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_');
+
+    match inner.split_once('.') {
+        Some(("answer", q)) if name_like(q) => Some(env.answer(q)),
+        Some(("var", name)) if name_like(name) => Some(env.var(name)),
+        Some(("env", name)) if !name.is_empty() => Some(env.env_var(name).map(Value::Str)),
+        _ => Expr::parse(inner).ok().map(|e| e.eval(env)),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::expr::fixture::env;
+
+    #[test]
+    fn placeholders() {
+        let env = env();
+
+        assert_eq!(render("You picked {{answer.q1}} — proceed?", &env), "You picked yes — proceed?");
+        assert_eq!(render("{{ var.name }}: {{var.count}}/{{count * 2}}", &env), "weid: 4/8");
+        assert_eq!(render("[{{answer.other}}{{var.missing}}]", &env), "[]");
+        assert_eq!(render("no placeholders", &env), "no placeholders");
+        assert_eq!(render("{{oops.}} {{ 1 + }} {{unclosed", &env), "{{oops.}} {{ 1 + }} {{unclosed");
+        assert_eq!(render("{{env.USER}}{{env.WEID_NOT_SET}}", &env), "ada");
+    }
+}
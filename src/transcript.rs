@@ -0,0 +1,272 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::prompt::{Menu, Pick, Prompter};
+use crate::querylist::{AnswerId, QueryId};
+use crate::report::Answered;
+
+// One line of a transcript. Times are seconds since the Unix epoch.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Entry {
+    // a query was shown, and something was chosen
+    Ask {
+        asked_at: f64,
+        at: f64,
+        query: Shown,
+        choice: Chosen,
+    },
+    // an outcome printed something
+    Output {
+        at: f64,
+        text: String,
+    },
+}
+
+// A query as it was shown: after its placeholders were expanded, and with
+// only the answers whose guards held
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Shown {
+    id: QueryId,
+    label: Option<String>,
+    text: String,
+    answers: Vec<ShownAnswer>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ShownAnswer {
+    id: AnswerId,
+    text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+}
+
+impl From<&Menu> for Shown {
+    fn from(menu: &Menu) -> Self {
+        Shown {
+            id: menu.qid,
+            label: menu.label.clone(),
+            text: menu.text.clone(),
+            answers: menu.answer_ids.iter().zip(&menu.answers).zip(&menu.values)
+                .map(|((id, text), value)| ShownAnswer { id: id.clone(), text: text.clone(), value: value.clone() })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Chosen {
+    Answer(ShownAnswer),
+    Back,
+    Cancel,
+}
+
+fn now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64())
+}
+
+// Writes down everything another prompter shows and is told, one JSON
+// entry per line, as the session goes
+pub struct Recorder<'p, W: Write> {
+    inner: Box<dyn Prompter + 'p>,
+    log: W,
+}
+
+impl<'p, W: Write> Recorder<'p, W> {
+    pub fn new(inner: Box<dyn Prompter + 'p>, log: W) -> Self {
+        Recorder { inner, log }
+    }
+
+    fn write(&mut self, entry: &Entry) -> Result<()> {
+        serde_json::to_writer(&mut self.log, entry)?;
+        writeln!(self.log)?;
+        self.log.flush()?;
+        Ok(())
+    }
+}
+
+impl<'p> Recorder<'p, File> {
+    pub fn create<P: AsRef<Path>>(inner: Box<dyn Prompter + 'p>, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let log = File::create(path).with_context(|| format!("{}: could not record the session", path.display()))?;
+        Ok(Recorder::new(inner, log))
+    }
+}
+
+impl<'p, W: Write> Prompter for Recorder<'p, W> {
+    fn ask(&mut self, menu: &Menu) -> Result<Pick> {
+        let asked_at = now();
+        let pick = self.inner.ask(menu)?;
+        let choice = match pick {
+            Pick::Answer(i) => Chosen::Answer(ShownAnswer {
+                id: menu.answer_ids[i].clone(),
+                text: menu.answers[i].clone(),
+                value: menu.values[i].clone(),
+            }),
+            Pick::Back => Chosen::Back,
+            Pick::Cancel => Chosen::Cancel,
+        };
+        self.write(&Entry::Ask { asked_at, at: now(), query: menu.into(), choice })?;
+        Ok(pick)
+    }
+
+    fn output(&mut self, text: &str) -> Result<()> {
+        self.write(&Entry::Output { at: now(), text: text.to_string() })?;
+        self.inner.output(text)
+    }
+
+    fn answered(&mut self, answered: &Answered) -> Result<()> {
+        self.inner.answered(answered)
+    }
+
+    fn took_back(&mut self, aid: &AnswerId) -> Result<()> {
+        self.inner.took_back(aid)
+    }
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Entry>> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("{}: could not read the transcript", path.display()))?;
+    read(BufReader::new(file)).map_err(|e| anyhow!("{}: {}", path.display(), e))
+}
+
+pub fn read<R: BufRead>(input: R) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (n, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        };
+        entries.push(serde_json::from_str(&line).map_err(|e| anyhow!("line {}: {}", n + 1, e))?);
+    };
+    Ok(entries)
+}
+
+// Makes the choices in a transcript again. Each query asked has to be the
+// one that was recorded, shown the same way with the same answers; the
+// replay stops with an error at the first one that isn't, or once the
+// transcript runs out. What outcomes print isn't compared, since it can
+// change from run to run without the flow changing.
+pub struct Replayer {
+    asks: VecDeque<(Shown, Chosen)>,
+    // how many queries have been replayed, for error messages
+    replayed: usize,
+}
+
+impl Replayer {
+    pub fn new(entries: Vec<Entry>) -> Self {
+        let asks = entries.into_iter()
+            .filter_map(|e| match e {
+                Entry::Ask { query, choice, .. } => Some((query, choice)),
+                Entry::Output { .. } => None,
+            })
+            .collect();
+        Replayer { asks, replayed: 0 }
+    }
+}
+
+impl Prompter for Replayer {
+    fn ask(&mut self, menu: &Menu) -> Result<Pick> {
+        let shown = Shown::from(menu);
+        let Some((recorded, choice)) = self.asks.pop_front() else {
+            bail!("Replay stopped after {} queries: the transcript ends before query {} ({:?})", self.replayed, shown.id, shown.text);
+        };
+        self.replayed += 1;
+
+        if recorded != shown {
+            bail!(
+                "Replay diverged at query #{}: the transcript has {} ({:?}, answers {:?}) but the session asked {} ({:?}, answers {:?})",
+                self.replayed,
+                recorded.id, recorded.text, recorded.answers.iter().map(|a| &a.text).collect::<Vec<_>>(),
+                shown.id, shown.text, menu.answers,
+            );
+        };
+        match choice {
+            Chosen::Answer(answer) => match menu.answer_ids.iter().position(|a| *a == answer.id) {
+                Some(i) => Ok(Pick::Answer(i)),
+                // only a transcript that was edited or damaged gets here
+                None => bail!(
+                    "Replay diverged at query #{}: the transcript chose {:?} ({:?}), which is not one of the answers to query {}",
+                    self.replayed, answer.id, answer.text, shown.id,
+                ),
+            },
+            Chosen::Back => Ok(Pick::Back),
+            Chosen::Cancel => Ok(Pick::Cancel),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::batch::BatchPrompter;
+
+    fn menu(text: &str) -> Menu {
+        Menu {
+            qid: 0,
+            label: Some("deploy".to_string()),
+            text: text.to_string(),
+            answers: vec!["yes".to_string(), "no".to_string()],
+            answer_ids: vec![AnswerId::new(0, 0), AnswerId::new(0, 1)],
+            values: vec![Some("deploy".to_string()), None],
+            can_go_back: true,
+        }
+    }
+
+    fn record() -> Vec<Entry> {
+        let mut log = Vec::new();
+        let batch = BatchPrompter::new(vec!["no".to_string(), "b".to_string()]);
+        let mut recorder = Recorder::new(Box::new(batch), &mut log);
+        recorder.ask(&menu("Deploy?")).unwrap();
+        recorder.output("deploying\n").unwrap();
+        recorder.ask(&menu("Deploy?")).unwrap();
+        drop(recorder);
+        read(log.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn recording() {
+        let entries = record();
+        assert_eq!(entries.len(), 3);
+        let Entry::Ask { asked_at, at, query, choice } = &entries[0] else {
+            panic!("{:?}", entries[0]);
+        };
+        assert!(*asked_at > 0.0 && at >= asked_at);
+        assert_eq!(query, &Shown::from(&menu("Deploy?")));
+        assert_eq!(query.answers[0].value, Some("deploy".to_string()));
+        assert_eq!(choice, &Chosen::Answer(ShownAnswer { id: AnswerId::new(0, 1), text: "no".to_string(), value: None }));
+        assert!(matches!(&entries[1], Entry::Output { text, .. } if text == "deploying\n"));
+        assert!(matches!(&entries[2], Entry::Ask { choice: Chosen::Back, .. }));
+
+        let line = serde_json::to_string(&entries[2]).unwrap();
+        assert!(line.starts_with(r#"{"event":"ask","#) && line.ends_with(r#""choice":"back"}"#), "{}", line);
+    }
+
+    #[test]
+    fn replay() {
+        let mut replayer = Replayer::new(record());
+        assert_eq!(replayer.ask(&menu("Deploy?")).unwrap(), Pick::Answer(1));
+        assert_eq!(replayer.ask(&menu("Deploy?")).unwrap(), Pick::Back);
+        let err = replayer.ask(&menu("Deploy?")).unwrap_err();
+        assert_eq!(err.to_string(), r#"Replay stopped after 2 queries: the transcript ends before query 0 ("Deploy?")"#);
+
+        let mut replayer = Replayer::new(record());
+        let err = replayer.ask(&menu("Deploy now?")).unwrap_err();
+        assert!(err.to_string().starts_with(r#"Replay diverged at query #1: the transcript has 0 ("Deploy?", answers ["yes", "no"]) but the session asked 0 ("Deploy now?""#), "{}", err);
+
+        let mut edited = record();
+        if let Entry::Ask { choice, .. } = &mut edited[0] {
+            *choice = Chosen::Answer(ShownAnswer { id: AnswerId::new(0, 5), text: "maybe".to_string(), value: None });
+        };
+        let err = Replayer::new(edited).ask(&menu("Deploy?")).unwrap_err();
+        assert_eq!(err.to_string(), r#"Replay diverged at query #1: the transcript chose q0a5 ("maybe"), which is not one of the answers to query 0"#);
+    }
+}
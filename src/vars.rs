@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{stdout, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+use crate::script::ScriptFormat;
+
+// A session variable. Untagged, so scripts can write `value = 3` or
+// `value = "three"` directly.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+// Guesses the type from the text, falling back to a string, the way a
+// command line value like `--set count=3` should be read.
+impl FromStr for Value {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(b) = s.parse() {
+            Ok(Value::Bool(b))
+        }
+        else if let Ok(i) = s.parse() {
+            Ok(Value::Int(i))
+        }
+        else if let Ok(x) = s.parse() {
+            Ok(Value::Float(x))
+        }
+        else {
+            Ok(Value::Str(s.to_string()))
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Int(i)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Float(x)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s)
+    }
+}
+
+// The variables of a session, shared by all of its outcomes
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Vars {
+    vars: BTreeMap<String, Value>,
+}
+
+impl Vars {
+    pub fn new() -> Self {
+        Vars::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.vars.get(name)
+    }
+
+    // Returns the value that was replaced, if any
+    pub fn set<V: Into<Value>>(&mut self, name: &str, value: V) -> Option<Value> {
+        self.vars.insert(name.to_string(), value.into())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.vars.remove(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.vars.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    // Writes the variables out, picking the format from the extension the
+    // same way script files do. A path of "-" writes JSON to stdout.
+    pub fn dump<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if path == Path::new("-") {
+            let text = serde_json::to_string_pretty(self)?;
+            writeln!(stdout(), "{}", text)?;
+            return Ok(());
+        };
+
+        let text = match ScriptFormat::from_path(path)? {
+            ScriptFormat::Toml => toml::to_string_pretty(self)
+                .map_err(|e| anyhow!("{}: {}", path.display(), e))?,
+            ScriptFormat::Json => serde_json::to_string_pretty(self)? + "\n",
+        };
+        fs::write(path, text).with_context(|| format!("{}: could not write variables", path.display()))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn values() {
+        assert_eq!("true".parse::<Value>().unwrap(), Value::Bool(true));
+        assert_eq!("-3".parse::<Value>().unwrap(), Value::Int(-3));
+        assert_eq!("2.5".parse::<Value>().unwrap(), Value::Float(2.5));
+        assert_eq!("prod".parse::<Value>().unwrap(), Value::Str("prod".to_string()));
+
+        let v: Vec<Value> = serde_json::from_str(r#"[false, 1, 1.5, "x"]"#).unwrap();
+        assert_eq!(v, [false.into(), 1.into(), 1.5.into(), "x".into()]);
+    }
+
+    #[test]
+    fn vars() {
+        let mut vars = Vars::new();
+        assert_eq!(vars.set("count", 1), None);
+        assert_eq!(vars.set("count", 2), Some(Value::Int(1)));
+        vars.set("target", "staging");
+
+        assert_eq!(vars.get("count"), Some(&Value::Int(2)));
+        assert_eq!(serde_json::to_string(&vars).unwrap(), r#"{"count":2,"target":"staging"}"#);
+        assert_eq!(toml::to_string(&vars).unwrap(), "count = 2\ntarget = \"staging\"\n");
+    }
+}